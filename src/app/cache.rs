@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    app::cli::CacheCommand,
+    core::{
+        cache::MetadataCache,
+        config::Config,
+        error::{ErrorCode, PipeBoomResult},
+    },
+    ipc::commands::{IpcCommand, IpcResponse, send_command},
+};
+
+/// Runs a cache command against the running service, or directly against the
+/// cache file when the service isn't running, which is the only time the
+/// config is read
+pub async fn run_cache_command(
+    command: CacheCommand,
+    socket_path: PathBuf,
+    config_path: &Path,
+) -> PipeBoomResult<()> {
+    let ipc_command = match command {
+        CacheCommand::Stats => IpcCommand::CacheStats,
+        CacheCommand::Clear => IpcCommand::CacheClear,
+        CacheCommand::Export => IpcCommand::CacheExport,
+    };
+
+    let response = match send_command(socket_path, ipc_command).await {
+        Ok(response) => response,
+        Err(e) if e.code() == ErrorCode::IpcUnavailable => {
+            log::debug!(
+                "Service unavailable ({:#}), using the cache file directly",
+                e
+            );
+            run_local(command, &Config::load(config_path)?)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    match response {
+        IpcResponse::CacheStats(stats) => println!("{:#?}", stats),
+        IpcResponse::CacheEntries(entries) => {
+            println!("{}", serde_json::to_string_pretty(&entries)?)
+        }
+        IpcResponse::Success => println!("Metadata cache cleared"),
+        IpcResponse::Error(e) => return Err(e.into()),
+        other => println!("{:#?}", other),
+    }
+
+    Ok(())
+}
+
+fn run_local(command: CacheCommand, config: &Config) -> PipeBoomResult<IpcResponse> {
+    let mut cache = MetadataCache::load(&config.cache);
+
+    Ok(match command {
+        CacheCommand::Stats => IpcResponse::CacheStats(cache.stats()),
+        CacheCommand::Clear => {
+            cache.clear()?;
+            IpcResponse::Success
+        }
+        CacheCommand::Export => IpcResponse::CacheEntries(cache.export()),
+    })
+}
@@ -9,13 +9,16 @@ use log::LevelFilter;
 
 use crate::ipc::commands::IpcCommand;
 
+const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
     #[command(subcommand)]
     pub command: Option<CliCommand>,
 
-    /// Override poll interval (seconds)
+    /// Override poll interval while playing, in seconds or as e.g. `500ms`
     #[arg(long, value_parser = parse_duration, default_value = "1")]
     pub poll_interval: Duration,
 
@@ -30,6 +33,18 @@ pub struct Cli {
     /// Override socket path
     #[arg(long, default_value_os_t = home_dir().unwrap_or(temp_dir()).join(".pipeboom.sock"))]
     pub socket_path: PathBuf,
+
+    /// Override config file path
+    #[arg(long, default_value_os_t = home_dir().unwrap_or(temp_dir()).join(".config/pipeboom/config.toml"))]
+    pub config: PathBuf,
+
+    /// Run the service in the background, detached from the terminal
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Override pidfile path, which also keeps a second service from starting
+    #[arg(long, default_value_os_t = home_dir().unwrap_or(temp_dir()).join(".pipeboom.pid"))]
+    pub pid_file: PathBuf,
 }
 
 #[derive(Subcommand, Debug)]
@@ -41,6 +56,28 @@ pub enum CliCommand {
     /// Service control commands
     #[command(subcommand)]
     Service(IpcCommand),
+    /// Metadata cache commands
+    #[command(subcommand)]
+    Cache(CacheCommand),
+    /// Override rule commands
+    #[command(subcommand)]
+    Overrides(OverridesCommand),
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum CacheCommand {
+    /// Show metadata cache statistics
+    Stats,
+    /// Remove all cached metadata
+    Clear,
+    /// Print all cached metadata as JSON
+    Export,
+}
+
+#[derive(Subcommand, Debug, Clone, Copy)]
+pub enum OverridesCommand {
+    /// Show which override rule matches the current song
+    Test,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -64,14 +101,49 @@ impl From<LogLevel> for LevelFilter {
     }
 }
 
+/// Parses plain seconds (`2`, `0.5`) or a humantime duration (`500ms`, `2s`)
 fn parse_duration(s: &str) -> Result<Duration, String> {
-    if let Ok(seconds) = s.parse::<u8>() {
-        if (1..=10).contains(&seconds) {
-            Ok(Duration::from_secs(seconds.into()))
-        } else {
-            Err("Must be between 1-10".into())
+    let duration = match s.parse::<f64>() {
+        Ok(seconds) => {
+            Duration::try_from_secs_f64(seconds).map_err(|e| format!("Invalid duration: {}", e))?
         }
+        Err(_) => humantime::parse_duration(s).map_err(|e| e.to_string())?,
+    };
+
+    if (MIN_POLL_INTERVAL..=MAX_POLL_INTERVAL).contains(&duration) {
+        Ok(duration)
     } else {
-        Err("Not a number".into())
+        Err(format!(
+            "Must be between {} and {}",
+            humantime::format_duration(MIN_POLL_INTERVAL),
+            humantime::format_duration(MAX_POLL_INTERVAL)
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_poll_intervals() {
+        // (input, expected)
+        let cases = [
+            ("1", Some(Duration::from_secs(1))),
+            ("0.5", Some(Duration::from_millis(500))),
+            ("500ms", Some(Duration::from_millis(500))),
+            ("10s", Some(MAX_POLL_INTERVAL)),
+            ("0.05", None),
+            ("11", None),
+            ("-1", None),
+            ("NaN", None),
+            ("inf", None),
+            ("1e30", None),
+            ("soon", None),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(parse_duration(input).ok(), expected, "{}", input);
+        }
     }
 }
@@ -1,220 +1,1247 @@
-use std::time::Duration;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use crate::{
+    app::{
+        publisher::PresencePublisher,
+        scheduler::{PollContext, PollScheduler},
+    },
     core::{
-        error::{PipeBoomError, PipeBoomResult},
-        models::PlayerState,
+        cache::MetadataCache,
+        clock::PlaybackClock,
+        config::{Config, PartyMode, PresenceConfig},
+        error::{ErrorCode, PipeBoomError, PipeBoomResult},
+        events::{Event, EventBus, Presence},
+        models::{PlayerState, Song, SongDetails},
+        overrides::Overrides,
+        state::{ControllerState, StateMachine},
+        utils::current_time_as_u64,
     },
     integrations::{
-        apple_music::{get_current_song, get_is_open, get_player_state},
-        discord::DiscordClient,
-        itunes_api::get_details,
+        apple_music::{
+            ProcessProbe, get_current_song, get_player_state, get_playlist_position,
+            get_track_location,
+        },
+        discord::DiscordConnector,
+        lyrics::{Lyrics, LyricsProvider},
+        metadata::ProviderChain,
     },
 };
 use tokio::{
-    sync::{mpsc, oneshot},
-    time::sleep,
+    sync::{mpsc, oneshot, watch},
+    time::{Instant, sleep, sleep_until},
 };
 
+/// Reconnection attempts before the controller gives up
+const MAX_RECONNECT_ATTEMPTS: u32 = 8;
+/// Longest wait between reconnection attempts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How long stopping waits for Discord to clear the activity
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
 #[derive(Debug)]
 pub enum Control {
     Start,
     Stop,
     Shutdown,
-    GetStatus(oneshot::Sender<bool>),
+    /// Track shown in the presence, if any
+    GetCurrentTrack(oneshot::Sender<Option<ShownTrack>>),
+    /// Applies a reloaded configuration. Cache and HTTP settings only change
+    /// after a restart.
+    Reload(Box<Config>),
+}
+
+/// Current track with override rules applied, as reported over IPC
+#[derive(Debug, Clone)]
+pub struct ShownTrack {
+    /// Identity of the track as the player reports it, before overrides
+    pub identity: String,
+    pub song: Song,
+    /// Set once the background lookup finished
+    pub details: Option<SongDetails>,
+    /// Whether an override rule hides the track
+    pub hidden: bool,
+}
+
+/// Track currently shown in the presence, with its resolved details and
+/// lyrics once the background lookups finished
+struct CurrentTrack {
+    identity: String,
+    song: Song,
+    details: Option<SongDetails>,
+    lyrics: Option<Lyrics>,
+    /// Position within the current playlist, looked up once per track
+    playlist_position: Option<(u32, u32)>,
+}
+
+/// Track shown in the "last listened" activity after playback stops
+struct LastPlayed {
+    song: Song,
+    details: SongDetails,
+    /// When playback stopped, in seconds since the epoch
+    stopped_at: u64,
+}
+
+/// Result of a background lookup for a track
+enum Resolved {
+    Metadata(Box<SongDetails>),
+    Lyrics(Option<Lyrics>),
+    PlaylistPosition(Option<(u32, u32)>),
 }
 
 pub struct Controller {
-    discord_client: Option<DiscordClient>,
+    /// Sends the published presence over the current Discord connection
+    publisher: Option<PresencePublisher>,
+    /// Discord connections opened so far, to tell their errors apart
+    connections: u64,
+    /// Errors of Discord connections, tagged with the connection's ID
+    publisher_errors_tx: mpsc::UnboundedSender<(u64, PipeBoomError)>,
+    publisher_errors_rx: mpsc::UnboundedReceiver<(u64, PipeBoomError)>,
+    /// Activity templates, followed by the publisher across reloads
+    templates: watch::Sender<PresenceConfig>,
+    /// Presence published last, so unchanged ones aren't sent again
+    last_presence: Option<Presence>,
     app_name: &'static str,
+    /// Checks whether Discord and the music app are running
+    probe: Arc<dyn ProcessProbe>,
+    connector: Arc<dyn DiscordConnector>,
     poll_interval: Duration,
-    is_running: bool,
+    scheduler: PollScheduler,
+    /// Delay before the next poll while active, picked by the scheduler
+    next_poll: Duration,
+    config: Config,
+    cache: Arc<Mutex<MetadataCache>>,
+    providers: Arc<ProviderChain>,
+    overrides: Overrides,
+    /// Set when lyrics are enabled
+    lyrics: Option<Arc<LyricsProvider>>,
+    current: Option<CurrentTrack>,
+    clock: PlaybackClock,
+    /// When the next lyric line starts
+    next_lyric_at: Option<Instant>,
+    resolved_tx: mpsc::UnboundedSender<(String, Resolved)>,
+    resolved_rx: mpsc::UnboundedReceiver<(String, Resolved)>,
+    events: EventBus,
+    /// Player state seen by the last cycle
+    player_state: Option<PlayerState>,
+    last_played: Option<LastPlayed>,
+    /// When playback stopped, in seconds since the epoch
+    idle_since: Option<u64>,
+    /// Whether polling is suspended after the idle timeout
+    idle: bool,
+    state: StateMachine,
+    /// Failed Discord connections since the controller was last active
+    reconnect_attempts: u32,
+    /// Code and message of the error that made the controller fail
+    failure: Option<(ErrorCode, String)>,
+    /// Code of the last published cycle error and the state it happened in,
+    /// so an error repeated on every poll is only published once
+    last_error: Option<(ControllerState, ErrorCode)>,
 }
 
 impl Controller {
-    pub fn new(app_name: &'static str, poll_interval: Duration) -> Self {
+    pub fn new(
+        app_name: &'static str,
+        poll_interval: Duration,
+        config: Config,
+        cache: Arc<Mutex<MetadataCache>>,
+        events: EventBus,
+        probe: Arc<dyn ProcessProbe>,
+        connector: Arc<dyn DiscordConnector>,
+    ) -> Self {
+        let providers = Arc::new(ProviderChain::new(&config, cache.clone()));
+        let lyrics = config
+            .lyrics
+            .enabled
+            .then(|| Arc::new(LyricsProvider::new(&config)));
+        let overrides = Overrides::load(config.overrides.path());
+        let (resolved_tx, resolved_rx) = mpsc::unbounded_channel();
+        let scheduler = PollScheduler::new(poll_interval, &config.polling);
+        let (publisher_errors_tx, publisher_errors_rx) = mpsc::unbounded_channel();
+        let (templates, _) = watch::channel(config.presence.clone());
+
         Self {
-            discord_client: None,
+            publisher: None,
+            connections: 0,
+            publisher_errors_tx,
+            publisher_errors_rx,
+            templates,
+            last_presence: None,
             app_name,
+            probe,
+            connector,
             poll_interval,
-            is_running: false,
+            scheduler,
+            next_poll: poll_interval,
+            config,
+            cache,
+            providers,
+            lyrics,
+            overrides,
+            current: None,
+            clock: PlaybackClock::default(),
+            next_lyric_at: None,
+            resolved_tx,
+            resolved_rx,
+            events,
+            player_state: None,
+            last_played: None,
+            idle_since: None,
+            idle: false,
+            state: StateMachine::new(),
+            reconnect_attempts: 0,
+            failure: None,
+            last_error: None,
         }
     }
 
-    pub async fn run(mut self, mut control_rx: mpsc::UnboundedReceiver<Control>) {
+    /// Continues from the state left by a previous controller, so a restart
+    /// keeps its failure and errors visible
+    pub fn with_state(mut self, state: StateMachine) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Starts the player and handles control messages until shutdown.
+    /// Returns an error if the controller fails, so it can be restarted.
+    pub async fn run(
+        mut self,
+        control_rx: &mut mpsc::UnboundedReceiver<Control>,
+    ) -> PipeBoomResult<()> {
+        // A controller that panicked left its state behind
+        if !matches!(
+            self.state.state(),
+            ControllerState::Stopped | ControllerState::Failed
+        ) {
+            self.state.transition(ControllerState::Failed)?;
+        }
+        if let Err(e) = self.start().await {
+            log::error!("Failed to start player: {:#}", e);
+        }
+
         loop {
+            // Stays failed until restarted, and fail() already disconnected
+            if let Some((code, message)) = self.failure.take() {
+                return Err(PipeBoomError::new(
+                    code,
+                    format!("Player controller failed: {}", message),
+                ));
+            }
+
+            let polling = self.is_polling();
+            let poll_delay = self.poll_delay();
+
             tokio::select! {
                 Some(control) = control_rx.recv() => {
                     match control {
                         Control::Start => {
                             if let Err(e) = self.start().await {
-                                log::error!("Failed to start player: {}", e);
+                                log::error!("Failed to start player: {:#}", e);
                             }
                         }
                         Control::Stop => {
                             if let Err(e) = self.stop().await {
-                                log::error!("Failed to stop player: {}", e);
+                                log::error!("Failed to stop player: {:#}", e);
                             }
                         }
                         Control::Shutdown => {
                             log::info!("Player controller shutting down");
                             let _ = self.stop().await;
-                            break;
+                            return Ok(());
                         }
-                        Control::GetStatus(sender) => {
-                            let _ = sender.send(self.is_running);
+                        Control::GetCurrentTrack(sender) => {
+                            let _ = sender.send(self.shown_track());
                         }
+                        Control::Reload(config) => self.reload(*config),
                     }
                 }
-                _ = sleep(self.poll_interval), if self.is_running => {
-                    if let Err(e) = self.run_cycle().await {
-                        if e.is_recoverable() {
-                            if let PipeBoomError::Discord(_) = e {
-                                if let Err(err) = self.initialize_discord_client() {
-                                    log::error!("Failed to reinitialize Discord client: {}", err);
-                                };
-                            };
-                            log::warn!("Recoverable player error: {}", e);
-                        } else {
-                            log::error!("Fatal player error: {}", e);
-                            let _ = self.stop().await;
+                Some((identity, resolved)) = self.resolved_rx.recv() => {
+                    self.apply_resolved(identity, resolved);
+                }
+                Some((connection, error)) = self.publisher_errors_rx.recv() => {
+                    // Errors of connections that were replaced are stale
+                    if self.publisher.as_ref().is_some_and(|p| p.id == connection) {
+                        self.handle_cycle_error(error);
+                    }
+                }
+                _ = sleep_until(self.next_lyric_at.unwrap_or_else(Instant::now)), if self.next_lyric_at.is_some() => {
+                    self.update_activity();
+                }
+                _ = sleep(poll_delay), if polling => {
+                    if self.state.is(ControllerState::Active) {
+                        match self.run_cycle().await {
+                            Ok(()) => self.last_error = None,
+                            Err(e) => self.handle_cycle_error(e),
                         }
+                    } else if let Err(e) = self.check_applications() {
+                        log::error!("Failed to connect to Discord: {:#}", e);
                     }
                 }
             }
         }
     }
 
+    fn reload(&mut self, config: Config) {
+        log::info!("Applying reloaded configuration");
+
+        self.providers = Arc::new(ProviderChain::new(&config, self.cache.clone()));
+        self.lyrics = config
+            .lyrics
+            .enabled
+            .then(|| Arc::new(LyricsProvider::new(&config)));
+        self.overrides = Overrides::load(config.overrides.path());
+        self.scheduler = PollScheduler::new(self.poll_interval, &config.polling);
+        if config.presence.party == PartyMode::Playlist
+            && self.config.presence.party != PartyMode::Playlist
+        {
+            if let Some(current) = &self.current {
+                self.resolve_playlist_position(current.identity.clone());
+            }
+        }
+        self.templates.send_replace(config.presence.clone());
+        self.config = config;
+
+        // Sends the presence again with the new templates
+        self.last_presence = None;
+        if self.player_state == Some(PlayerState::Playing) {
+            self.update_activity();
+        }
+    }
+
+    fn is_polling(&self) -> bool {
+        matches!(
+            self.state.state(),
+            ControllerState::WaitingForApps | ControllerState::Active | ControllerState::Backoff
+        )
+    }
+
+    /// Scheduled poll delay while active, the app-closed delay while waiting
+    /// for apps, and a growing wait between reconnections while backing off
+    fn poll_delay(&self) -> Duration {
+        match self.state.state() {
+            ControllerState::Active => return self.next_poll,
+            ControllerState::Backoff => {}
+            _ => return self.scheduler.delay(PollContext::AppClosed),
+        }
+
+        let exponent = self.reconnect_attempts.saturating_sub(1).min(16);
+        self.poll_interval
+            .saturating_mul(1 << exponent)
+            .min(MAX_BACKOFF)
+    }
+
     async fn start(&mut self) -> PipeBoomResult<()> {
-        if self.is_running {
+        if !matches!(
+            self.state.state(),
+            ControllerState::Stopped | ControllerState::Failed
+        ) {
             return Ok(());
         }
 
         log::info!("Starting player controller");
-        self.wait_for_applications().await?;
-        self.initialize_discord_client()?;
-        self.is_running = true;
+        self.reconnect_attempts = 0;
+        self.state.transition(ControllerState::WaitingForApps)?;
 
-        Ok(())
+        self.check_applications()
     }
 
     async fn stop(&mut self) -> PipeBoomResult<()> {
-        if !self.is_running {
+        if self.state.is(ControllerState::Stopped) {
             return Ok(());
         }
 
         log::info!("Stopping player controller");
-        self.is_running = false;
+        self.state.transition(ControllerState::Stopped)?;
+        if let Some(publisher) = self.disconnect() {
+            publisher.close(DISCONNECT_TIMEOUT).await;
+        }
 
-        if let Some(client) = self.discord_client.as_mut() {
-            if client.is_connected {
-                if let Err(e) = client.close() {
-                    log::warn!("Error closing Discord client: {}", e);
-                }
+        Ok(())
+    }
+
+    /// Connects to Discord, backing off if that fails
+    fn connect(&mut self) -> PipeBoomResult<()> {
+        self.state.transition(ControllerState::Connecting)?;
+
+        match self.initialize_discord_client() {
+            Ok(()) => {
+                self.reconnect_attempts = 0;
+                self.next_poll = self.poll_interval;
+                self.state.transition(ControllerState::Active)
+            }
+            Err(e) => {
+                log::warn!("Failed to connect to Discord: {:#}", e);
+                self.state.record_error(&e);
+                self.back_off()
             }
         }
-        self.discord_client = None;
+    }
+
+    /// Drops the Discord connection and waits before reconnecting, or gives
+    /// up after too many attempts
+    fn back_off(&mut self) -> PipeBoomResult<()> {
+        self.disconnect();
+        self.reconnect_attempts += 1;
+
+        if self.reconnect_attempts > MAX_RECONNECT_ATTEMPTS {
+            let error = PipeBoomError::discord(format!(
+                "Gave up after {} reconnection attempts",
+                MAX_RECONNECT_ATTEMPTS
+            ));
+            return self.fail(&error);
+        }
+
+        self.state.transition(ControllerState::Backoff)?;
+        log::info!("Reconnecting to Discord in {:?}", self.poll_delay());
 
         Ok(())
     }
 
-    async fn wait_for_applications(&self) -> PipeBoomResult<()> {
-        log::info!("Waiting for Discord and {}...", self.app_name);
+    fn fail(&mut self, error: &PipeBoomError) -> PipeBoomResult<()> {
+        log::error!("Player controller failed: {:#}", error);
+        self.failure = Some((error.code(), format!("{:#}", error)));
+        self.events.publish(Event::Error {
+            code: error.code(),
+            message: format!("{:#}", error),
+        });
+        self.disconnect();
+        self.state.transition(ControllerState::Failed)?;
+        self.state.record_error(error);
 
-        loop {
-            let discord_open = get_is_open("Discord").map_err(|e| {
-                PipeBoomError::Internal(format!("Failed to check if Discord is open: {}", e))
-            })?;
+        Ok(())
+    }
 
-            let music_open = get_is_open(self.app_name).map_err(|e| {
-                PipeBoomError::Internal(format!(
-                    "Failed to check if {} is open: {}",
-                    self.app_name, e
-                ))
-            })?;
+    fn handle_cycle_error(&mut self, error: PipeBoomError) {
+        self.state.record_error(&error);
 
-            if discord_open && music_open {
-                log::info!("Both Discord and {} are now open", self.app_name);
-                break;
+        let last_error = Some((self.state.state(), error.code()));
+        if self.last_error != last_error {
+            self.last_error = last_error;
+            self.events.publish(Event::Error {
+                code: error.code(),
+                message: format!("{:#}", error),
+            });
+        }
+
+        let result = match error.code() {
+            ErrorCode::DiscordNotRunning | ErrorCode::DiscordIpc => {
+                log::warn!("Discord error: {:#}", error);
+                self.back_off()
+            }
+            _ if error.is_retryable() => {
+                log::warn!("Recoverable player error: {:#}", error);
+                Ok(())
             }
+            _ => self.fail(&error),
+        };
 
-            log::debug!(
-                "Waiting for apps - Discord: {}, {}: {}",
-                discord_open,
-                self.app_name,
-                music_open
-            );
-            sleep(self.poll_interval).await;
+        if let Err(e) = result {
+            log::error!("Failed to handle player error: {:#}", e);
+        }
+    }
+
+    /// Forgets the current track and tells the publisher to close the Discord
+    /// connection. Returns the publisher, to wait for it to finish.
+    fn disconnect(&mut self) -> Option<PresencePublisher> {
+        let publisher = self.publisher.take();
+        if publisher.is_some() {
+            self.events.publish(Event::DiscordDisconnected);
+        }
+        self.last_presence = None;
+        self.current = None;
+        self.next_lyric_at = None;
+        self.player_state = None;
+        self.last_played = None;
+        self.idle_since = None;
+        self.idle = false;
+
+        publisher
+    }
+
+    /// Connects to Discord once it and the music app are both open.
+    /// Otherwise waits for them, checking again on the next poll.
+    fn check_applications(&mut self) -> PipeBoomResult<()> {
+        let missing = match self.missing_applications() {
+            Ok(missing) => missing,
+            Err(e) => return self.fail(&e),
+        };
+
+        if missing.is_empty() {
+            log::info!("Both Discord and {} are now open", self.app_name);
+            return self.connect();
+        }
+
+        if !self.state.is(ControllerState::WaitingForApps) {
+            self.disconnect();
+            self.reconnect_attempts = 0;
+            self.state.transition(ControllerState::WaitingForApps)?;
+        }
+
+        if self.state.waiting_for() != missing {
+            log::info!("Waiting for {}...", missing.join(" and "));
+            self.state.set_waiting_for(missing);
         }
 
         Ok(())
     }
 
+    /// Names of the apps that aren't open
+    fn missing_applications(&self) -> PipeBoomResult<Vec<String>> {
+        let mut missing = Vec::new();
+
+        for app in ["Discord", self.app_name] {
+            let open = self
+                .probe
+                .is_open(app)
+                .map_err(|e| e.context(format!("Failed to check if {} is open", app)))?;
+            if !open {
+                missing.push(app.to_string());
+            }
+        }
+
+        Ok(missing)
+    }
+
     fn initialize_discord_client(&mut self) -> PipeBoomResult<()> {
         log::info!("Initializing Discord client");
 
-        let mut discord_client = DiscordClient::new();
-        discord_client.connect()?;
+        let discord_client = self.connector.connect(self.config.presence.clone())?;
 
-        self.discord_client = Some(discord_client);
+        self.connections += 1;
+        self.publisher = Some(PresencePublisher::spawn(
+            self.connections,
+            discord_client,
+            &self.events,
+            self.templates.subscribe(),
+            self.publisher_errors_tx.clone(),
+        ));
+        self.last_presence = None;
         log::info!("Discord client connected successfully");
+        self.events.publish(Event::DiscordConnected);
 
         Ok(())
     }
 
     async fn run_cycle(&mut self) -> PipeBoomResult<()> {
-        let discord_client = self.discord_client.as_mut().ok_or_else(|| {
-            PipeBoomError::Internal("Discord client not initialized in player cycle".to_string())
-        })?;
+        if self.publisher.is_none() {
+            return Err(PipeBoomError::internal(
+                "Discord client not initialized in player cycle".to_string(),
+            ));
+        }
 
-        if !get_is_open("Discord").map_err(|e| {
-            PipeBoomError::Internal(format!("Failed to check Discord status: {}", e))
-        })? {
+        if !self
+            .probe
+            .is_open("Discord")
+            .map_err(|e| e.context("Failed to check Discord status"))?
+        {
             log::info!("Discord closed. Stopping player");
-            return Err(PipeBoomError::Discord(
-                "Discord application closed".to_string(),
+            return Err(PipeBoomError::new(
+                ErrorCode::DiscordNotRunning,
+                "Discord application closed",
             ));
         }
 
-        if !get_is_open(self.app_name).map_err(|e| {
-            PipeBoomError::Internal(format!("Failed to check {} status: {}", self.app_name, e))
-        })? {
-            log::info!(
-                "{} closed. Clearing activity and stopping player",
-                self.app_name
-            );
-            discord_client.clear_activity()?;
-            return Err(PipeBoomError::AppleMusic(format!(
-                "{} closed",
-                self.app_name
-            )));
+        if !self
+            .probe
+            .is_open(self.app_name)
+            .map_err(|e| e.context(format!("Failed to check {} status", self.app_name)))?
+        {
+            log::info!("{} closed", self.app_name);
+            self.show_stopped();
+            self.next_poll = self.scheduler.delay(if self.idle {
+                PollContext::Suspended
+            } else {
+                PollContext::AppClosed
+            });
+            return Err(PipeBoomError::new(
+                ErrorCode::MusicNotRunning,
+                format!("{} closed", self.app_name),
+            ));
         }
 
-        let player_state = get_player_state(self.app_name)
-            .map_err(|e| PipeBoomError::Internal(format!("Failed to get player state: {}", e)))?;
+        let player_state =
+            get_player_state(self.app_name).map_err(|e| e.context("Failed to get player state"))?;
+        let playing = player_state == PlayerState::Playing;
+        if self.player_state != Some(player_state) {
+            self.player_state = Some(player_state);
+            self.clock.set_playing(playing);
+            self.events.publish(Event::PlaybackStateChanged {
+                state: player_state,
+            });
+        }
 
         match player_state {
             PlayerState::Playing => {
-                if let Some(song) = get_current_song(self.app_name).map_err(|e| {
-                    PipeBoomError::Internal(format!("Failed to get current song: {}", e))
-                })? {
+                if let Some(song) = get_current_song(self.app_name)
+                    .map_err(|e| e.context("Failed to get current song"))?
+                {
                     log::debug!("Currently playing: {} - {}", song.artist, song.name);
+                    self.resume();
 
-                    let details = get_details(&song).await?;
-                    log::debug!("Song details retrieved successfully");
+                    let position = Duration::from_secs_f32(song.player_position.max(0.0));
+                    let remaining = (song.duration > 0.0).then(|| {
+                        Duration::from_secs_f32((song.duration - song.player_position).max(0.0))
+                    });
+                    self.next_poll = self.scheduler.delay(PollContext::Playing { remaining });
 
-                    discord_client.update_activity(&song, &details)?;
+                    self.track_song(song);
+                    if self.clock.update(position, playing) {
+                        log::debug!("Seek detected, now at {:?}", position);
+                        self.events.publish(Event::Seeked {
+                            position: position.as_secs_f32(),
+                        });
+                        // The same track at a new position still needs sending
+                        self.last_presence = None;
+                    }
+                    self.update_activity();
                 } else {
-                    log::debug!("Player is playing but no song info available. Clearing activity.");
-                    discord_client.clear_activity()?;
+                    log::debug!("Player is playing but no song info available");
+                    self.show_stopped();
                 }
             }
             _ => {
-                log::debug!("Player state is {:?}. Clearing activity.", player_state);
-                discord_client.clear_activity()?;
+                log::debug!("Player state is {:?}", player_state);
+                self.show_stopped();
             }
         }
 
         Ok(())
     }
+
+    /// Forgets the last played track and idle time once playback resumes
+    fn resume(&mut self) {
+        if self.idle {
+            log::info!("Playback resumed, resuming polling");
+        }
+
+        self.idle = false;
+        self.idle_since = None;
+        self.last_played = None;
+    }
+
+    /// Shows the last played track for a while after playback stops, and
+    /// clears the activity afterwards. Once nothing played for the idle
+    /// timeout, polling is suspended until playback resumes.
+    fn show_stopped(&mut self) {
+        self.next_lyric_at = None;
+        let now = current_time_as_u64().unwrap_or_default();
+
+        if self.last_played.is_none() {
+            self.last_played = self.current.as_ref().map(|current| LastPlayed {
+                song: current.song.clone(),
+                details: current
+                    .details
+                    .clone()
+                    .unwrap_or_else(|| self.providers.placeholder(&current.song)),
+                stopped_at: now,
+            });
+        }
+
+        let idle_for = now.saturating_sub(*self.idle_since.get_or_insert(now));
+        let idle_timeout = self.config.polling.idle_timeout;
+        if !self.idle && idle_timeout > 0 && idle_for >= idle_timeout {
+            log::info!(
+                "Nothing played for {}s, clearing activity and suspending polling",
+                idle_for
+            );
+            self.idle = true;
+        }
+        self.next_poll = self.scheduler.delay(if self.idle {
+            PollContext::Suspended
+        } else {
+            PollContext::Idle
+        });
+
+        let last_listened = self.config.presence.last_listened;
+        let showing = !self.idle && last_listened > 0;
+        let last_played = self
+            .last_played
+            .as_ref()
+            .filter(|last| showing && now.saturating_sub(last.stopped_at) < last_listened);
+
+        let presence = match last_played {
+            Some(last) => {
+                let mut song = last.song.clone();
+                let mut details = last.details.clone();
+                if self.overrides.apply(&mut song, &mut details) {
+                    Presence::LastListened {
+                        song: Box::new(song),
+                        details: Box::new(details),
+                        stopped_at: last.stopped_at,
+                    }
+                } else {
+                    Presence::Cleared
+                }
+            }
+            None => Presence::Cleared,
+        };
+        self.publish_presence(presence);
+    }
+
+    /// Starts tracking `song` if it is a new track. It is shown with its
+    /// cached details or a placeholder while its metadata and lyrics are
+    /// resolved in the background, as displayed after override rules.
+    /// Tracks hidden by a rule aren't looked up at all.
+    fn track_song(&mut self, song: Song) {
+        let identity = song.identity();
+
+        if let Some(current) = self.current.as_mut().filter(|c| c.identity == identity) {
+            current.song = song;
+            return;
+        }
+
+        self.clock.reset();
+        self.overrides.reload_if_changed();
+        let Some(displayed) = self.overrides.displayed(&song) else {
+            log::debug!(
+                "{} is hidden by an override rule, skipping lookups",
+                identity
+            );
+            self.current = Some(CurrentTrack {
+                identity,
+                song,
+                details: None,
+                lyrics: None,
+                playlist_position: None,
+            });
+            return;
+        };
+
+        self.events.publish(Event::TrackChanged {
+            song: Box::new(displayed.clone()),
+        });
+
+        let cached = self.providers.cached(&displayed);
+        if cached.is_none() {
+            log::debug!("Resolving metadata for {} in the background", identity);
+
+            let providers = Arc::clone(&self.providers);
+            let resolved_tx = self.resolved_tx.clone();
+            let song = displayed.clone();
+            let identity = identity.clone();
+            tokio::spawn(async move {
+                let details = providers.lookup(&song).await;
+                let _ = resolved_tx.send((identity, Resolved::Metadata(Box::new(details))));
+            });
+        }
+
+        if self.config.presence.party == PartyMode::Playlist {
+            self.resolve_playlist_position(identity.clone());
+        }
+
+        if let Some(lyrics) = &self.lyrics {
+            let lyrics = Arc::clone(lyrics);
+            let resolved_tx = self.resolved_tx.clone();
+            let app_name = self.app_name;
+            let song = displayed.clone();
+            let identity = identity.clone();
+            tokio::spawn(async move {
+                let location = run_blocking(move || get_track_location(app_name))
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::debug!("Failed to get track location: {:#}", e);
+                        None
+                    });
+
+                let found = lyrics
+                    .get(&song, location.as_deref().map(Path::new))
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::debug!("Failed to get lyrics: {:#}", e);
+                        None
+                    });
+                let _ = resolved_tx.send((identity, Resolved::Lyrics(found)));
+            });
+        }
+
+        self.current = Some(CurrentTrack {
+            identity,
+            song,
+            details: cached,
+            lyrics: None,
+            playlist_position: None,
+        });
+    }
+
+    /// Looks up the track's position within the current playlist in the
+    /// background, as listing a large playlist takes a while
+    fn resolve_playlist_position(&self, identity: String) {
+        let resolved_tx = self.resolved_tx.clone();
+        let app_name = self.app_name;
+
+        tokio::spawn(async move {
+            let position = run_blocking(move || get_playlist_position(app_name))
+                .await
+                .unwrap_or_else(|e| {
+                    log::debug!("Failed to get playlist position: {:#}", e);
+                    None
+                });
+            let _ = resolved_tx.send((identity, Resolved::PlaylistPosition(position)));
+        });
+    }
+
+    /// Stores the result of a background lookup and updates the activity,
+    /// unless the track changed in the meantime
+    fn apply_resolved(&mut self, identity: String, resolved: Resolved) {
+        let Some(current) = self.current.as_mut().filter(|c| c.identity == identity) else {
+            log::debug!("Dropping stale lookup result for {}", identity);
+            return;
+        };
+
+        match resolved {
+            Resolved::Metadata(mut details) => {
+                log::debug!("Song details retrieved successfully");
+                current.details = Some((*details).clone());
+
+                let mut song = current.song.clone();
+                if self.overrides.apply(&mut song, &mut details) {
+                    self.events
+                        .publish(Event::MetadataResolved { identity, details });
+                }
+            }
+            Resolved::Lyrics(lyrics) => {
+                log::debug!("Lyrics found: {}", lyrics.is_some());
+                current.lyrics = lyrics;
+            }
+            Resolved::PlaylistPosition(position) => {
+                log::debug!("Playlist position: {:?}", position);
+                current.playlist_position = position;
+            }
+        }
+
+        self.update_activity();
+    }
+
+    /// Publishes the current track's presence, with the lyric line at the
+    /// clock's position, and schedules the next lyric update
+    fn update_activity(&mut self) {
+        self.next_lyric_at = None;
+        self.overrides.reload_if_changed();
+
+        let Some(current) = &self.current else {
+            return;
+        };
+
+        let mut details = current
+            .details
+            .clone()
+            .unwrap_or_else(|| self.providers.placeholder(&current.song));
+        let mut song = current.song.clone();
+        if !self.overrides.apply(&mut song, &mut details) {
+            log::debug!("{} is hidden by an override rule", current.identity);
+            return self.publish_presence(Presence::Cleared);
+        }
+
+        let position = self.clock.position();
+        if let Some(position) = position {
+            song.player_position = position.as_secs_f32();
+        }
+
+        let min_line_duration = Duration::from_millis(self.config.lyrics.min_line_duration);
+        let lyrics = current.lyrics.as_ref().zip(position);
+        let lyric = lyrics
+            .and_then(|(lyrics, position)| lyrics.line_at(position, min_line_duration))
+            .map(|line| line.text.as_str());
+        self.next_lyric_at = lyrics.and_then(|(lyrics, position)| {
+            lyrics
+                .next_change(position, min_line_duration)
+                .map(|start| Instant::now() + (start - position))
+        });
+
+        let party = Self::party_position(self.config.presence.party, current, &song, &details);
+        let presence = Presence::Playing {
+            song: Box::new(song),
+            details: Box::new(details),
+            party,
+            lyric: lyric.map(str::to_string),
+        };
+        self.publish_presence(presence);
+    }
+
+    /// Hands `presence` to the publisher, unless Discord already shows it or
+    /// isn't connected
+    fn publish_presence(&mut self, presence: Presence) {
+        if self.publisher.is_none() {
+            return;
+        }
+        let unchanged = self
+            .last_presence
+            .as_ref()
+            .is_some_and(|last| last.shows_the_same_as(&presence));
+        if unchanged {
+            return;
+        }
+
+        self.last_presence = Some(presence.clone());
+        self.events.publish(Event::PresenceChanged { presence });
+    }
+
+    /// The current track as displayed, with override rules applied
+    fn shown_track(&self) -> Option<ShownTrack> {
+        let current = self.current.as_ref()?;
+        let mut song = current.song.clone();
+        let mut details = current.details.clone();
+
+        let rule = self.overrides.find(&current.song).map(|(_, rule)| rule);
+        if let Some(rule) = rule {
+            rule.apply_to_song(&mut song);
+            if let Some(details) = details.as_mut() {
+                rule.apply_to_details(details);
+            }
+        }
+        let hidden = rule.is_some_and(|rule| rule.hide);
+
+        Some(ShownTrack {
+            identity: current.identity.clone(),
+            song,
+            details,
+            hidden,
+        })
+    }
+
+    fn party_position(
+        mode: PartyMode,
+        current: &CurrentTrack,
+        song: &Song,
+        details: &SongDetails,
+    ) -> Option<(u32, u32)> {
+        match mode {
+            PartyMode::Off => None,
+            PartyMode::Album => {
+                if song.track_number > 0 && song.track_count > 0 {
+                    Some((song.track_number, song.track_count))
+                } else {
+                    details.track_number.zip(details.track_count)
+                }
+            }
+            PartyMode::Playlist => current.playlist_position,
+        }
+    }
+}
+
+/// Runs a blocking call, such as an Osascript one, off the async runtime
+async fn run_blocking<T, F>(f: F) -> PipeBoomResult<T>
+where
+    F: FnOnce() -> PipeBoomResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| PipeBoomError::internal("Blocking task failed").with_source(e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, path::PathBuf};
+
+    use tokio::sync::broadcast;
+
+    use super::*;
+    use crate::{
+        core::{config::CacheConfig, models::test_song, utils::temp_path},
+        integrations::discord::DiscordClient,
+    };
+
+    /// Apps that are "running", changed by the test as it goes
+    #[derive(Default)]
+    struct FakeProbe {
+        open: Mutex<HashSet<&'static str>>,
+    }
+
+    impl FakeProbe {
+        fn set_open(&self, apps: &[&'static str]) {
+            *self.open.lock().unwrap() = apps.iter().copied().collect();
+        }
+    }
+
+    impl ProcessProbe for FakeProbe {
+        fn is_open(&self, app_name: &str) -> PipeBoomResult<bool> {
+            Ok(self.open.lock().unwrap().contains(app_name))
+        }
+    }
+
+    /// Hands out clients that never touch Discord's socket, or refuses to
+    /// connect as if Discord weren't listening
+    struct FakeConnector {
+        refuse: bool,
+    }
+
+    impl DiscordConnector for FakeConnector {
+        fn connect(&self, templates: PresenceConfig) -> PipeBoomResult<DiscordClient> {
+            if self.refuse {
+                return Err(PipeBoomError::discord("Connection refused"));
+            }
+
+            Ok(DiscordClient::new(templates))
+        }
+    }
+
+    /// Removes a test's cache file once the test ends
+    struct CacheFile(PathBuf);
+
+    impl Drop for CacheFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    const POLL_INTERVAL: Duration = Duration::from_secs(1);
+    /// How long controllers may take to react to control messages
+    const PROMPTLY: Duration = Duration::from_millis(500);
+
+    fn controller(
+        name: &str,
+        probe: Arc<FakeProbe>,
+    ) -> (Controller, broadcast::Receiver<Event>, CacheFile) {
+        let config = Config {
+            offline: true,
+            ..Default::default()
+        };
+        let path = temp_path(&format!("controller-{}.json", name));
+        let cache = MetadataCache::load(&CacheConfig {
+            path: Some(path.clone()),
+            ..Default::default()
+        });
+        let events = EventBus::new();
+        let receiver = events.subscribe();
+        let controller = Controller::new(
+            "Music",
+            POLL_INTERVAL,
+            config,
+            Arc::new(Mutex::new(cache)),
+            events,
+            probe,
+            Arc::new(FakeConnector { refuse: false }),
+        );
+
+        (controller, receiver, CacheFile(path))
+    }
+
+    /// Waits until `state` reaches `expected`, failing if that takes too long
+    async fn reaches(state: &StateMachine, expected: ControllerState) {
+        let reached = tokio::time::timeout(PROMPTLY, async {
+            while !state.is(expected) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await;
+
+        assert!(reached.is_ok(), "stuck in {:?}", state.state());
+    }
+
+    /// Names of the events published so far
+    fn received(receiver: &mut broadcast::Receiver<Event>) -> Vec<String> {
+        std::iter::from_fn(|| receiver.try_recv().ok())
+            .map(|event| {
+                serde_json::to_value(event).unwrap()["event"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn waits_for_apps_then_connects_then_backs_off() {
+        let probe = Arc::new(FakeProbe::default());
+        probe.set_open(&["Music"]);
+        let (mut controller, mut events, _cache) = controller("connects", Arc::clone(&probe));
+
+        controller.start().await.unwrap();
+        assert_eq!(controller.state.state(), ControllerState::WaitingForApps);
+        assert_eq!(controller.state.waiting_for(), ["Discord"]);
+        assert_eq!(
+            controller.poll_delay(),
+            Duration::from_millis(controller.config.polling.max_interval)
+        );
+
+        probe.set_open(&["Discord", "Music"]);
+        controller.check_applications().unwrap();
+        assert_eq!(controller.state.state(), ControllerState::Active);
+        assert!(controller.publisher.is_some());
+        assert_eq!(controller.poll_delay(), POLL_INTERVAL);
+
+        // Music being closed is reported once, however many polls it lasts
+        probe.set_open(&["Discord"]);
+        for _ in 0..3 {
+            let error = controller.run_cycle().await.unwrap_err();
+            assert_eq!(error.code(), ErrorCode::MusicNotRunning);
+            controller.handle_cycle_error(error);
+            assert_eq!(controller.state.state(), ControllerState::Active);
+        }
+
+        probe.set_open(&["Music"]);
+        let error = controller.run_cycle().await.unwrap_err();
+        assert_eq!(error.code(), ErrorCode::DiscordNotRunning);
+        controller.handle_cycle_error(error);
+        assert_eq!(controller.state.state(), ControllerState::Backoff);
+        assert!(controller.publisher.is_none());
+        assert_eq!(controller.reconnect_attempts, 1);
+        assert_eq!(controller.poll_delay(), POLL_INTERVAL);
+
+        let status = controller.state.status();
+        assert!(
+            status.states[&ControllerState::Active]
+                .last_error
+                .as_deref()
+                .is_some_and(|error| error.contains("Discord application closed"))
+        );
+
+        assert_eq!(
+            received(&mut events),
+            [
+                "discord_connected",
+                "presence_changed",
+                "error",
+                "error",
+                "discord_disconnected"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn publishes_the_presence_only_when_it_changes() {
+        let probe = Arc::new(FakeProbe::default());
+        probe.set_open(&["Discord", "Music"]);
+        let (mut controller, mut events, _cache) = controller("presence", probe);
+        controller.start().await.unwrap();
+        let song = test_song("Fleetwood Mac", "Dreams", "Rumours", "Fleetwood Mac");
+        controller.current = Some(CurrentTrack {
+            identity: song.identity(),
+            song,
+            details: None,
+            lyrics: None,
+            playlist_position: None,
+        });
+
+        // Later polls of the same track only move its position
+        for position in [10.0, 15.0, 20.0] {
+            controller.current.as_mut().unwrap().song.player_position = position;
+            controller.update_activity();
+        }
+        // The publisher confirms from its own task
+        let sent = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let Ok(Event::PresenceSent { title, .. }) = events.recv().await {
+                    break title;
+                }
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(sent, "Dreams");
+        assert!(received(&mut events).is_empty());
+
+        controller.current.as_mut().unwrap().song.name = "Songbird".to_string();
+        controller.update_activity();
+        controller.update_activity();
+        assert_eq!(received(&mut events), ["presence_changed"]);
+    }
+
+    #[tokio::test]
+    async fn backs_off_longer_after_each_failed_reconnection() {
+        let probe = Arc::new(FakeProbe::default());
+        probe.set_open(&["Discord", "Music"]);
+        let (mut controller, _events, _cache) = controller("backoff", Arc::clone(&probe));
+        controller.start().await.unwrap();
+        assert_eq!(controller.state.state(), ControllerState::Active);
+
+        // (attempts, expected delay)
+        let cases = [
+            (1, POLL_INTERVAL),
+            (2, POLL_INTERVAL * 2),
+            (4, POLL_INTERVAL * 8),
+            (8, POLL_INTERVAL * 128),
+        ];
+
+        for (attempts, expected) in cases {
+            controller.reconnect_attempts = attempts;
+            controller.state.transition(ControllerState::Backoff).ok();
+            assert_eq!(controller.poll_delay(), expected.min(MAX_BACKOFF));
+        }
+
+        controller.reconnect_attempts = MAX_RECONNECT_ATTEMPTS;
+        controller.back_off().unwrap();
+        assert_eq!(controller.state.state(), ControllerState::Failed);
+        assert!(controller.failure.is_some());
+    }
+
+    #[tokio::test]
+    async fn restarted_controllers_keep_the_state() {
+        let probe = Arc::new(FakeProbe::default());
+        probe.set_open(&["Discord", "Music"]);
+        let state = StateMachine::new();
+        let (failed, _events, _failed_cache) = controller("failed", Arc::clone(&probe));
+        let mut failed = failed.with_state(state.clone());
+        failed.start().await.unwrap();
+        failed.reconnect_attempts = MAX_RECONNECT_ATTEMPTS;
+        failed.back_off().unwrap();
+        drop(failed);
+
+        let status = state.status();
+        assert_eq!(status.state, ControllerState::Failed);
+        assert!(status.states[&ControllerState::Failed].last_error.is_some());
+
+        let (restarted, _events, _restarted_cache) = controller("restarted", probe);
+        let mut restarted = restarted.with_state(state.clone());
+        restarted.start().await.unwrap();
+        assert_eq!(state.state(), ControllerState::Active);
+        assert!(
+            state.status().states[&ControllerState::Failed]
+                .last_error
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_again_when_apps_close_while_backing_off() {
+        let probe = Arc::new(FakeProbe::default());
+        probe.set_open(&["Discord", "Music"]);
+        let (mut controller, _events, _cache) = controller("waits-again", Arc::clone(&probe));
+        controller.start().await.unwrap();
+        controller.back_off().unwrap();
+        assert_eq!(controller.state.state(), ControllerState::Backoff);
+
+        probe.set_open(&[]);
+        controller.check_applications().unwrap();
+        assert_eq!(controller.state.state(), ControllerState::WaitingForApps);
+        assert_eq!(controller.state.waiting_for(), ["Discord", "Music"]);
+        assert_eq!(controller.reconnect_attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn stops_and_shuts_down_promptly_while_not_polling_discord() {
+        let cases = [
+            // (open apps, Discord refuses connections, state to stop in)
+            (vec![], false, ControllerState::WaitingForApps),
+            (vec!["Music"], false, ControllerState::WaitingForApps),
+            (vec!["Discord", "Music"], true, ControllerState::Backoff),
+        ];
+
+        for (open, refuse, waiting) in cases {
+            let probe = Arc::new(FakeProbe::default());
+            probe.set_open(&open);
+            let (mut controller, _events, _cache) = controller("run", probe);
+            controller.connector = Arc::new(FakeConnector { refuse });
+            let state = controller.state.clone();
+
+            let (control_tx, mut control_rx) = mpsc::unbounded_channel();
+            let run = tokio::spawn(async move { controller.run(&mut control_rx).await });
+            reaches(&state, waiting).await;
+
+            control_tx.send(Control::Stop).unwrap();
+            reaches(&state, ControllerState::Stopped).await;
+
+            control_tx.send(Control::Shutdown).unwrap();
+            let result = tokio::time::timeout(PROMPTLY, run).await;
+            assert!(
+                matches!(result, Ok(Ok(Ok(())))),
+                "{:?} didn't shut down",
+                waiting
+            );
+        }
+    }
 }
@@ -0,0 +1,126 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    path::Path,
+    process,
+};
+
+use crate::core::error::{ErrorCode, PipeBoomError, PipeBoomResult};
+
+/// Exclusive lock on the pidfile, held while the service runs so a second
+/// instance can't take over its socket
+pub struct InstanceLock {
+    file: File,
+    /// PID left in the file by an instance that exited without cleaning up
+    stale_pid: Option<u32>,
+}
+
+impl InstanceLock {
+    pub fn acquire(path: &Path) -> PipeBoomResult<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        let previous_pid = contents.trim().parse::<u32>().ok();
+
+        // SAFETY: the descriptor stays open for as long as `file` lives
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::WouldBlock {
+                return Err(
+                    PipeBoomError::io(format!("Failed to lock {:?}", path)).with_source(error)
+                );
+            }
+
+            return Err(PipeBoomError::new(
+                ErrorCode::InstanceAlreadyRunning,
+                match previous_pid {
+                    Some(pid) => format!(
+                        "PipeBoom is already running (PID {}). Stop it with `pipeboom service shutdown` first",
+                        pid
+                    ),
+                    None => format!("Another PipeBoom instance holds the lock on {:?}", path),
+                },
+            ));
+        }
+
+        let mut lock = Self {
+            file,
+            stale_pid: previous_pid,
+        };
+        lock.write_pid()?;
+
+        Ok(lock)
+    }
+
+    pub fn stale_pid(&self) -> Option<u32> {
+        self.stale_pid
+    }
+
+    /// Records the current process ID, which changes after daemonizing
+    pub fn write_pid(&mut self) -> PipeBoomResult<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        writeln!(self.file, "{}", process::id())?;
+        self.file.sync_all()?;
+
+        Ok(())
+    }
+}
+
+impl Drop for InstanceLock {
+    /// Empties the pidfile rather than removing it. Unlinking it while the
+    /// lock is held would let a new instance lock a fresh file at the same
+    /// path before this one exits.
+    fn drop(&mut self) {
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// Detaches from the terminal by forking, starting a new session, moving to
+/// `/` and pointing stdio at `/dev/null`. Paths must be absolute by then. Must
+/// be called before any other thread is started.
+pub fn daemonize() -> PipeBoomResult<()> {
+    // SAFETY: the process is still single-threaded
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error().into()),
+        0 => {}
+        pid => {
+            println!("PipeBoom is running in the background (PID {})", pid);
+            process::exit(0);
+        }
+    }
+
+    // SAFETY: plain syscalls without pointers
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // Files the service creates are only writable by the user.
+    // SAFETY: plain syscall without pointers
+    unsafe { libc::umask(0o022) };
+
+    // Don't keep the directory it was started from in use
+    std::env::set_current_dir("/")?;
+
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        // SAFETY: both descriptors are valid
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
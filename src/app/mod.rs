@@ -1,6 +1,12 @@
+pub mod cache;
 pub mod cli;
 mod controller;
+pub mod instance;
+pub mod overrides;
+mod publisher;
 mod runner;
+mod scheduler;
 pub mod setup;
+mod supervisor;
 
 pub use runner::*;
@@ -0,0 +1,45 @@
+use crate::{
+    app::cli::OverridesCommand,
+    core::{config::Config, error::PipeBoomResult, overrides::Overrides},
+    integrations::apple_music::{get_current_song, music_app_name},
+};
+
+pub fn run_overrides_command(command: OverridesCommand, config: &Config) -> PipeBoomResult<()> {
+    match command {
+        OverridesCommand::Test => test(config),
+    }
+}
+
+/// Prints the rule matching the current song and what it changes
+fn test(config: &Config) -> PipeBoomResult<()> {
+    let path = config.overrides.path();
+    println!("Overrides file: {:?}", path);
+    let overrides = Overrides::try_load(path)?;
+
+    let Some(song) = get_current_song(music_app_name())? else {
+        println!("No song is playing");
+        return Ok(());
+    };
+
+    println!(
+        "Current song: {} - {} ({}) [persistentID {}]",
+        song.artist, song.name, song.album, song.persistent_id
+    );
+
+    match overrides.find(&song) {
+        Some((index, rule)) => {
+            println!(
+                "Matched rule #{}{}",
+                index + 1,
+                rule.name
+                    .as_ref()
+                    .map(|name| format!(" ({})", name))
+                    .unwrap_or_default()
+            );
+            println!("{:#?}", rule);
+        }
+        None => println!("No rule matches"),
+    }
+
+    Ok(())
+}
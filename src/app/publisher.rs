@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use tokio::{
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc, watch,
+    },
+    task::JoinHandle,
+};
+
+use crate::{
+    core::{
+        config::PresenceConfig,
+        error::{PipeBoomError, PipeBoomResult},
+        events::{Event, EventBus, Presence},
+    },
+    integrations::discord::DiscordClient,
+};
+
+/// Sends the presence the controller publishes on the event bus to Discord.
+/// It runs as its own subscriber, so a slow Discord never holds up polling.
+pub struct PresencePublisher {
+    /// Identifies this connection in the errors it reports
+    pub id: u64,
+    handle: JoinHandle<()>,
+}
+
+impl PresencePublisher {
+    /// Takes over `client` and sends it every presence published on `events`
+    /// until Discord is disconnected. The first failure is reported on
+    /// `errors`, after which the connection is closed.
+    pub fn spawn(
+        id: u64,
+        client: DiscordClient,
+        events: &EventBus,
+        templates: watch::Receiver<PresenceConfig>,
+        errors: mpsc::UnboundedSender<(u64, PipeBoomError)>,
+    ) -> Self {
+        let receiver = events.subscribe();
+        let events = events.clone();
+
+        let handle = tokio::spawn(async move {
+            if let Err(e) = publish(client, receiver, &events, templates).await {
+                let _ = errors.send((id, e));
+            }
+        });
+
+        Self { id, handle }
+    }
+
+    /// Waits up to `timeout` for the connection to close, after
+    /// [`Event::DiscordDisconnected`] was published
+    pub async fn close(self, timeout: Duration) {
+        if tokio::time::timeout(timeout, self.handle).await.is_err() {
+            log::warn!("Discord connection didn't close within {:?}", timeout);
+        }
+    }
+}
+
+async fn publish(
+    mut client: DiscordClient,
+    mut receiver: broadcast::Receiver<Event>,
+    events: &EventBus,
+    mut templates: watch::Receiver<PresenceConfig>,
+) -> PipeBoomResult<()> {
+    let result = loop {
+        let presence = match receiver.recv().await {
+            Ok(Event::PresenceChanged { presence }) => presence,
+            Ok(Event::DiscordDisconnected) | Err(RecvError::Closed) => break Ok(()),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(missed)) => {
+                // The latest presence is still queued, so nothing is lost
+                log::debug!("Discord presence publisher skipped {} events", missed);
+                continue;
+            }
+        };
+
+        if templates.has_changed().unwrap_or_default() {
+            client.set_templates(templates.borrow_and_update().clone());
+        }
+
+        if let Err(e) = send(&mut client, &presence) {
+            break Err(e);
+        }
+
+        if let Presence::Playing { song, lyric, .. } = presence {
+            events.publish(Event::PresenceSent {
+                title: song.name,
+                artist: song.artist,
+                lyric,
+            });
+        }
+    };
+
+    if let Err(e) = client.close() {
+        log::warn!("Error closing Discord client: {:#}", e);
+    }
+
+    result
+}
+
+fn send(client: &mut DiscordClient, presence: &Presence) -> PipeBoomResult<()> {
+    match presence {
+        Presence::Playing {
+            song,
+            details,
+            party,
+            lyric,
+        } => client.update_activity(song, details, *party, lyric.as_deref()),
+        Presence::LastListened {
+            song,
+            details,
+            stopped_at,
+        } => client.update_last_listened(song, details, *stopped_at),
+        Presence::Cleared => client.clear_activity(),
+    }
+}
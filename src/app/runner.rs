@@ -1,19 +1,58 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use crate::{
-    app::controller::{Control, Controller},
-    core::{error::PipeBoomResult, models::PlayerState, utils::macos_ver},
-    integrations::apple_music::{get_current_song, get_is_open, get_player_state},
+    app::{
+        controller::{Control, Controller, ShownTrack},
+        supervisor::Supervisor,
+    },
+    core::{
+        cache::{self, FLUSH_INTERVAL, MetadataCache},
+        config::Config,
+        credits::Credits,
+        error::{ErrorCode, PipeBoomError, PipeBoomResult},
+        events::EventBus,
+        models::{PlayerState, ShareLinks},
+        overrides::Overrides,
+        state::{ControllerState, HealthStatus, StateMachine},
+    },
+    integrations::{
+        apple_music::{
+            SystemEventsProbe, get_current_song, get_is_open, get_player_state, music_app_name,
+        },
+        discord::IpcConnector,
+    },
     ipc::{
         commands::{IpcCommand, IpcResponse},
         server::IpcServer,
     },
 };
-use tokio::sync::{mpsc, oneshot};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+
+/// How long the controller gets to clear the activity and disconnect on shutdown
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+/// Name of the player controller in the supervisor
+const CONTROLLER: &str = "controller";
+/// How long IPC requests wait for the controller to answer, as it can be
+/// busy, restarting or given up on
+const CONTROLLER_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
 
 pub struct App {
     app_name: &'static str,
     control_tx: Option<mpsc::UnboundedSender<Control>>,
+    cache: Option<Arc<Mutex<MetadataCache>>>,
+    /// Latest configuration, used when the controller is restarted
+    config: Option<Arc<Mutex<Config>>>,
+    /// Shared with every controller, so restarts keep its state and errors
+    controller_state: StateMachine,
+    supervisor: Supervisor,
 }
 
 impl Default for App {
@@ -24,21 +63,13 @@ impl Default for App {
 
 impl App {
     pub fn new() -> Self {
-        let app_name = match macos_ver() {
-            Ok(ver) if ver >= 10.15 => "Music",
-            Ok(_) => "iTunes",
-            Err(e) => {
-                log::warn!(
-                    "Failed to determine macOS version: {}. Defaulting app name to 'Music'.",
-                    e
-                );
-                "Music"
-            }
-        };
-
         Self {
-            app_name,
+            app_name: music_app_name(),
             control_tx: None,
+            cache: None,
+            config: None,
+            controller_state: StateMachine::new(),
+            supervisor: Supervisor::new(),
         }
     }
 
@@ -46,26 +77,60 @@ impl App {
         &mut self,
         poll_interval: Duration,
         socket_path: PathBuf,
+        config: Config,
+        config_path: PathBuf,
     ) -> PipeBoomResult<()> {
-        let (mut ipc_server, mut request_rx) = IpcServer::new(socket_path);
+        let mut sigint = signal(SignalKind::interrupt())?;
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sighup = signal(SignalKind::hangup())?;
 
-        tokio::spawn(async move {
-            if let Err(e) = ipc_server.start().await {
-                log::error!("IPC server error: {}", e);
-            }
+        let events = EventBus::new();
+        let (ipc_server, mut request_rx) = IpcServer::new(socket_path, events.clone());
+
+        let ipc_server = Arc::new(ipc_server);
+        let ipc_handle = self.supervisor.spawn("ipc_server", move || {
+            let ipc_server = Arc::clone(&ipc_server);
+            async move { ipc_server.start().await }
         });
 
         let (player_control_tx, player_control_rx) = mpsc::unbounded_channel();
         self.control_tx = Some(player_control_tx);
 
-        let player_controller = Controller::new(self.app_name, poll_interval);
-        tokio::spawn(async move {
-            player_controller.run(player_control_rx).await;
+        let cache = Arc::new(Mutex::new(MetadataCache::load(&config.cache)));
+        self.cache = Some(cache.clone());
+
+        let config = Arc::new(Mutex::new(config));
+        self.config = Some(config.clone());
+
+        let flushed_cache = cache.clone();
+        let flush_handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            loop {
+                interval.tick().await;
+                cache::flush(&flushed_cache).await;
+            }
         });
 
-        log::info!("PipeBoom is ready for IPC commands");
+        // Restarted controllers keep reading from the same channel
+        let control_rx = Arc::new(tokio::sync::Mutex::new(player_control_rx));
+        let app_name = self.app_name;
+        let controller_state = self.controller_state.clone();
+        let controller_handle = self.supervisor.spawn(CONTROLLER, move || {
+            let controller = Controller::new(
+                app_name,
+                poll_interval,
+                config.lock().unwrap().clone(),
+                cache.clone(),
+                events.clone(),
+                Arc::new(SystemEventsProbe),
+                Arc::new(IpcConnector),
+            )
+            .with_state(controller_state.clone());
+            let control_rx = Arc::clone(&control_rx);
+            async move { controller.run(&mut *control_rx.lock().await).await }
+        });
 
-        let _ = self.handle_start().await;
+        log::info!("PipeBoom is ready for IPC commands");
 
         loop {
             tokio::select! {
@@ -75,6 +140,11 @@ impl App {
                         IpcCommand::Stop => self.handle_stop().await,
                         IpcCommand::CurrentSong => self.handle_get_current_song().await,
                         IpcCommand::Status => self.handle_get_status().await,
+                        IpcCommand::Share => self.handle_share().await,
+                        IpcCommand::Events => IpcResponse::Error(PipeBoomError::ipc("Events are streamed by the IPC server").into()),
+                        IpcCommand::CacheStats
+                        | IpcCommand::CacheClear
+                        | IpcCommand::CacheExport => self.handle_cache(&request.command),
                         IpcCommand::Shutdown => {
                             log::info!("Received shutdown command via IPC");
                             if request.response_tx.send(IpcResponse::Success).is_err() {
@@ -88,28 +158,98 @@ impl App {
                         log::warn!("Failed to send IPC response - client may have disconnected");
                     }
                 }
+                _ = sigint.recv() => {
+                    log::info!("Received SIGINT");
+                    break;
+                }
+                _ = sigterm.recv() => {
+                    log::info!("Received SIGTERM");
+                    break;
+                }
+                _ = sighup.recv() => {
+                    log::info!("Received SIGHUP, reloading configuration");
+                    self.reload_config(&config_path, poll_interval);
+                }
             }
         }
 
+        log::info!("PipeBoom shutting down");
+
         if let Some(tx) = &self.control_tx {
             let _ = tx.send(Control::Shutdown);
         }
+        if self.supervisor.status(CONTROLLER) == Some(HealthStatus::GaveUp) {
+            controller_handle.abort();
+        }
+        if timeout(SHUTDOWN_DEADLINE, controller_handle).await.is_err() {
+            log::warn!(
+                "Player controller didn't stop within {:?}, exiting anyway",
+                SHUTDOWN_DEADLINE
+            );
+        }
+
+        flush_handle.abort();
+        if let Some(cache) = &self.cache {
+            cache::flush(cache).await;
+        }
+
+        // Dropping the server removes its socket
+        ipc_handle.abort();
+        let _ = ipc_handle.await;
 
-        log::info!("PipeBoom shutting down");
         Ok(())
     }
 
+    /// Rereads the config file and hands it to the controller, keeping the
+    /// current configuration if the file is invalid
+    fn reload_config(&self, config_path: &Path, poll_interval: Duration) {
+        let loaded = Config::load(config_path).and_then(|config| {
+            config.polling.check_interval(poll_interval)?;
+            Ok(config)
+        });
+        let config = match loaded {
+            Ok(config) => config,
+            Err(e) => {
+                log::error!("Keeping the current configuration: {:#}", e);
+                return;
+            }
+        };
+
+        if let Some(current) = &self.config {
+            *current.lock().unwrap() = config.clone();
+        }
+        if let Some(tx) = &self.control_tx {
+            if tx.send(Control::Reload(Box::new(config))).is_err() {
+                log::warn!("Failed to send reloaded configuration to player controller");
+            }
+        }
+    }
+
     async fn handle_start(&mut self) -> IpcResponse {
         log::info!("Received start command via IPC");
 
+        if self.supervisor.revive(CONTROLLER) {
+            log::info!("Restarting the player controller after it was given up on");
+        }
+
         if let Some(tx) = &self.control_tx {
             if tx.send(Control::Start).is_err() {
                 return IpcResponse::Error(
-                    "Failed to send start command to player controller".to_string(),
+                    PipeBoomError::new(
+                        ErrorCode::PlayerUnavailable,
+                        "Failed to send start command to player controller",
+                    )
+                    .into(),
                 );
             }
         } else {
-            return IpcResponse::Error("Player controller not available".to_string());
+            return IpcResponse::Error(
+                PipeBoomError::new(
+                    ErrorCode::PlayerUnavailable,
+                    "Player controller not available",
+                )
+                .into(),
+            );
         }
 
         IpcResponse::Success
@@ -121,11 +261,21 @@ impl App {
         if let Some(tx) = &self.control_tx {
             if tx.send(Control::Stop).is_err() {
                 return IpcResponse::Error(
-                    "Failed to send stop command to player controller".to_string(),
+                    PipeBoomError::new(
+                        ErrorCode::PlayerUnavailable,
+                        "Failed to send stop command to player controller",
+                    )
+                    .into(),
                 );
             }
         } else {
-            return IpcResponse::Error("Player controller not available".to_string());
+            return IpcResponse::Error(
+                PipeBoomError::new(
+                    ErrorCode::PlayerUnavailable,
+                    "Player controller not available",
+                )
+                .into(),
+            );
         }
 
         IpcResponse::Success
@@ -134,24 +284,94 @@ impl App {
     async fn handle_get_current_song(&self) -> IpcResponse {
         match get_current_song(self.app_name) {
             Ok(song_opt) => {
-                if let Some(song) = song_opt {
+                // Shown as the presence shows it, or with the override rules
+                // applied here if the controller isn't tracking the song yet
+                let shown = match song_opt {
+                    Some(song) => match self
+                        .get_current_track()
+                        .await
+                        .filter(|track| track.identity == song.identity())
+                    {
+                        Some(track) => (!track.hidden).then_some((track.song, track.details)),
+                        None => self.overrides().displayed(&song).map(|song| (song, None)),
+                    },
+                    None => None,
+                };
+
+                if let Some((song, details)) = shown {
                     let state = get_player_state(self.app_name).unwrap_or(PlayerState::Unknown);
+                    let credits = Credits::parse(&song.artist, &song.name);
                     IpcResponse::CurrentSong {
                         title: Some(song.name),
                         artist: Some(song.artist),
                         album: Some(song.album),
+                        primary_artist: Some(credits.primary_artist),
+                        featured_artists: credits.featured_artists,
                         state,
+                        song_url: details
+                            .as_ref()
+                            .map(|details| details.song_url.clone())
+                            .filter(|url| !url.is_empty()),
+                        links: details.map(|details| details.links).unwrap_or_default(),
                     }
                 } else {
                     IpcResponse::CurrentSong {
                         title: None,
                         artist: None,
                         album: None,
+                        primary_artist: None,
+                        featured_artists: Vec::new(),
                         state: PlayerState::Stopped,
+                        song_url: None,
+                        links: ShareLinks::default(),
                     }
                 }
             }
-            Err(e) => IpcResponse::Error(format!("Failed to get current song: {}", e)),
+            Err(e) => IpcResponse::Error(e.context("Failed to get current song").into()),
+        }
+    }
+
+    async fn handle_share(&self) -> IpcResponse {
+        let track = self
+            .get_current_track()
+            .await
+            .filter(|track| !track.hidden)
+            .and_then(|track| Some((track.song, track.details?)));
+
+        match track {
+            Some((song, details)) => IpcResponse::Share {
+                title: song.name,
+                artist: song.artist,
+                song_url: Some(details.song_url).filter(|url| !url.is_empty()),
+                links: details.links,
+            },
+            None => IpcResponse::Error(
+                PipeBoomError::new(ErrorCode::PlayerNoTrack, "No resolved song is playing").into(),
+            ),
+        }
+    }
+
+    /// Override rules of the current configuration
+    fn overrides(&self) -> Overrides {
+        let path = match &self.config {
+            Some(config) => config.lock().unwrap().overrides.path(),
+            None => Config::default().overrides.path(),
+        };
+
+        Overrides::load(path)
+    }
+
+    async fn get_current_track(&self) -> Option<ShownTrack> {
+        let tx = self.control_tx.as_ref()?;
+        let (track_tx, track_rx) = oneshot::channel();
+        tx.send(Control::GetCurrentTrack(track_tx)).ok()?;
+
+        match timeout(CONTROLLER_REPLY_TIMEOUT, track_rx).await {
+            Ok(track) => track.ok().flatten(),
+            Err(_) => {
+                log::warn!("Player controller didn't report the current track in time");
+                None
+            }
         }
     }
 
@@ -159,22 +379,40 @@ impl App {
         let discord_open = get_is_open("Discord").unwrap_or(false);
         let music_open = get_is_open(self.app_name).unwrap_or(false);
 
-        let running = if let Some(tx) = &self.control_tx {
-            let (status_tx, status_rx) = oneshot::channel();
-            if tx.send(Control::GetStatus(status_tx)).is_ok() {
-                status_rx.await.unwrap_or(false)
-            } else {
-                false
-            }
-        } else {
-            false
-        };
+        // Read directly rather than asked for, so a busy, restarting or
+        // given up controller can't hold up the status or the runner loop
+        let controller = self.controller_state.status();
 
         IpcResponse::Status {
-            running,
+            running: controller.state == ControllerState::Active,
+            controller,
+            components: self.supervisor.health(),
             discord_connected: discord_open,
             discord_open,
             music_app_open: music_open,
         }
     }
+
+    fn handle_cache(&self, command: &IpcCommand) -> IpcResponse {
+        let Some(cache) = &self.cache else {
+            return IpcResponse::Error(
+                PipeBoomError::new(ErrorCode::PlayerUnavailable, "Metadata cache not available")
+                    .into(),
+            );
+        };
+        let mut cache = cache.lock().unwrap();
+
+        match command {
+            IpcCommand::CacheStats => IpcResponse::CacheStats(cache.stats()),
+            IpcCommand::CacheClear => match cache.clear() {
+                Ok(()) => {
+                    log::info!("Metadata cache cleared via IPC");
+                    IpcResponse::Success
+                }
+                Err(e) => IpcResponse::Error(e.context("Failed to clear metadata cache").into()),
+            },
+            IpcCommand::CacheExport => IpcResponse::CacheEntries(cache.export()),
+            _ => IpcResponse::Error(PipeBoomError::ipc("Not a cache command").into()),
+        }
+    }
 }
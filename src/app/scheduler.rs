@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use crate::core::config::PollingConfig;
+
+/// Polling speeds up this long before a track is expected to end
+const BOUNDARY_WINDOW: Duration = Duration::from_secs(3);
+/// Paused or stopped players are polled this many times less often
+const IDLE_FACTOR: u32 = 5;
+
+/// What the last poll found, which decides when to poll next
+#[derive(Debug, Clone, Copy)]
+pub enum PollContext {
+    /// Playing, with the time left in the track if it is known
+    Playing { remaining: Option<Duration> },
+    /// Paused, stopped or playing nothing
+    Idle,
+    /// The music app isn't running
+    AppClosed,
+    /// Nothing played for longer than the idle timeout
+    Suspended,
+}
+
+/// Picks the delay before the next poll from what the player is doing
+#[derive(Debug, Clone)]
+pub struct PollScheduler {
+    base: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl PollScheduler {
+    pub fn new(base: Duration, config: &PollingConfig) -> Self {
+        let min = Duration::from_millis(config.min_interval);
+        let max = Duration::from_millis(config.max_interval).max(min);
+
+        Self { base, min, max }
+    }
+
+    pub fn delay(&self, context: PollContext) -> Duration {
+        let delay = match context {
+            PollContext::Playing {
+                remaining: Some(remaining),
+            } if remaining <= BOUNDARY_WINDOW => self.min,
+            // Wake up when the boundary window starts rather than overshooting it
+            PollContext::Playing {
+                remaining: Some(remaining),
+            } => self.base.min(remaining - BOUNDARY_WINDOW),
+            PollContext::Playing { remaining: None } => self.base,
+            PollContext::Idle => self.base.saturating_mul(IDLE_FACTOR),
+            PollContext::AppClosed | PollContext::Suspended => self.max,
+        };
+
+        delay.clamp(self.min, self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn playing(remaining_ms: Option<u64>) -> PollContext {
+        PollContext::Playing {
+            remaining: remaining_ms.map(ms),
+        }
+    }
+
+    #[test]
+    fn picks_delays() {
+        let polling = PollingConfig::default();
+
+        let cases = [
+            // (base ms, context, expected ms)
+            (1500, playing(None), 1500),
+            (1500, playing(Some(60_000)), 1500),
+            // Wakes up as the boundary window starts, then polls fast within it
+            (1500, playing(Some(4000)), 1000),
+            (1500, playing(Some(3100)), 250),
+            (1500, playing(Some(3000)), 250),
+            (1500, playing(Some(0)), 250),
+            (1500, PollContext::Idle, 7500),
+            (10_000, PollContext::Idle, 30_000),
+            (1500, PollContext::AppClosed, 30_000),
+            (1500, PollContext::Suspended, 30_000),
+            // The base interval itself stays within the bounds
+            (100, playing(None), 250),
+            (60_000, playing(None), 30_000),
+        ];
+
+        for (base, context, expected) in cases {
+            let scheduler = PollScheduler::new(ms(base), &polling);
+            assert_eq!(
+                scheduler.delay(context),
+                ms(expected),
+                "{} {:?}",
+                base,
+                context
+            );
+        }
+    }
+}
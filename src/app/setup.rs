@@ -13,10 +13,10 @@ pub fn setup_launch_agent() -> PipeBoomResult<()> {
     let plist_dir = format!("{}/Library/LaunchAgents", home);
     let plist_file = format!("{}/{}.plist", plist_dir, service_name);
     let exe_path = std::env::current_exe()
-        .map_err(|e| PipeBoomError::Setup(format!("Failed to get current exe path: {e}")))?;
+        .map_err(|e| PipeBoomError::setup("Failed to get current exe path").with_source(e))?;
     let install_dir = exe_path
         .parent()
-        .ok_or_else(|| PipeBoomError::Setup("Failed to get binary directory".to_string()))?
+        .ok_or_else(|| PipeBoomError::setup("Failed to get binary directory".to_string()))?
         .to_string_lossy()
         .to_string();
 
@@ -65,7 +65,7 @@ pub fn setup_launch_agent() -> PipeBoomResult<()> {
         .output()?;
 
     if !output.status.success() {
-        return Err(PipeBoomError::Setup(format!(
+        return Err(PipeBoomError::setup(format!(
             "Failed to load Launch Agent: {}",
             String::from_utf8_lossy(&output.stderr)
         )));
@@ -85,7 +85,7 @@ pub fn uninstall_launch_agent() -> PipeBoomResult<()> {
     let plist_file = format!("{}/Library/LaunchAgents/{}.plist", home, BUNDLE_ID);
 
     if fs::metadata(&plist_file).is_err() {
-        return Err(PipeBoomError::Setup(format!(
+        return Err(PipeBoomError::setup(format!(
             "Launch Agent {} not found",
             plist_file
         )));
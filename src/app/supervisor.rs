@@ -0,0 +1,162 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    sync::Notify,
+    task::{AbortHandle, JoinHandle},
+    time::{Instant, sleep},
+};
+
+use crate::core::{
+    error::PipeBoomResult,
+    state::{ComponentHealth, HealthStatus},
+};
+
+/// Crashes within this window count towards giving up
+const CRASH_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Crashes within the window before a component is given up on
+const MAX_CRASHES: usize = 5;
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Aborts a task when dropped, so cancelling the supervisor cancels the
+/// component too
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Runs long-lived components, restarting them with backoff when they fail
+/// or panic, and giving up after too many crashes in a short time until
+/// they're revived
+#[derive(Debug, Clone, Default)]
+pub struct Supervisor {
+    health: Arc<Mutex<BTreeMap<&'static str, ComponentHealth>>>,
+    revivals: Arc<Mutex<BTreeMap<&'static str, Arc<Notify>>>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs the futures made by `start` one after another until one exits
+    /// cleanly. The returned handle finishes when the component stops. A
+    /// component that was given up on waits for [`Supervisor::revive`].
+    pub fn spawn<F, Fut>(&self, name: &'static str, mut start: F) -> JoinHandle<()>
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = PipeBoomResult<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let revival = Arc::clone(
+            self.revivals
+                .lock()
+                .unwrap()
+                .entry(name)
+                .or_insert_with(|| Arc::new(Notify::new())),
+        );
+
+        tokio::spawn(async move {
+            let mut crashes = VecDeque::new();
+
+            loop {
+                supervisor.update(name, HealthStatus::Running, None);
+
+                let mut task = tokio::spawn(start());
+                let _guard = AbortOnDrop(task.abort_handle());
+                let error = match (&mut task).await {
+                    Ok(Ok(())) => {
+                        log::debug!("{} stopped", name);
+                        supervisor.update(name, HealthStatus::Stopped, None);
+                        return;
+                    }
+                    Ok(Err(e)) => format!("{:#}", e),
+                    Err(e) => format!("Panicked: {}", e),
+                };
+                log::error!("{} failed: {}", name, error);
+
+                let now = Instant::now();
+                crashes.push_back(now);
+                while crashes
+                    .front()
+                    .is_some_and(|crash| now - *crash > CRASH_WINDOW)
+                {
+                    crashes.pop_front();
+                }
+
+                if crashes.len() > MAX_CRASHES {
+                    log::error!(
+                        "{} crashed {} times within {:?}, giving up",
+                        name,
+                        crashes.len(),
+                        CRASH_WINDOW
+                    );
+                    supervisor.update(name, HealthStatus::GaveUp, Some(error));
+
+                    revival.notified().await;
+                    log::info!("Reviving {}", name);
+                    crashes.clear();
+                    continue;
+                }
+
+                let delay = RESTART_BACKOFF
+                    .saturating_mul(1 << (crashes.len() - 1))
+                    .min(MAX_RESTART_BACKOFF);
+                log::info!("Restarting {} in {:?}", name, delay);
+                supervisor.update(name, HealthStatus::Restarting, Some(error));
+                sleep(delay).await;
+            }
+        })
+    }
+
+    /// Restarts a component that was given up on. Returns false if it wasn't.
+    pub fn revive(&self, name: &'static str) -> bool {
+        if self.status(name) != Some(HealthStatus::GaveUp) {
+            return false;
+        }
+
+        self.update(name, HealthStatus::Restarting, None);
+        if let Some(revival) = self.revivals.lock().unwrap().get(name) {
+            revival.notify_one();
+        }
+        true
+    }
+
+    pub fn status(&self, name: &'static str) -> Option<HealthStatus> {
+        self.health
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|component| component.status)
+    }
+
+    pub fn health(&self) -> Vec<ComponentHealth> {
+        self.health.lock().unwrap().values().cloned().collect()
+    }
+
+    fn update(&self, name: &'static str, status: HealthStatus, error: Option<String>) {
+        let mut health = self.health.lock().unwrap();
+        let component = health.entry(name).or_insert_with(|| ComponentHealth {
+            name: name.to_string(),
+            status,
+            restarts: 0,
+            last_error: None,
+        });
+
+        if status == HealthStatus::Restarting {
+            component.restarts += 1;
+        }
+        component.status = status;
+        if error.is_some() {
+            component.last_error = error;
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    config::CacheConfig, error::PipeBoomResult, models::SongDetails, utils::current_time_as_u64,
+};
+
+const CACHE_VERSION: u32 = 1;
+/// How often changed entries are written to disk
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// Resolved details, or `None` when no provider had a match
+    pub details: Option<SongDetails>,
+    pub stored_at: u64,
+    pub last_used: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: HashMap<String, CacheEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub path: PathBuf,
+    pub entries: usize,
+    pub negative_entries: usize,
+    pub expired_entries: usize,
+    pub max_entries: usize,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Resolved metadata keyed by track identity, persisted across restarts
+pub struct MetadataCache {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+    ttl: u64,
+    negative_ttl: u64,
+    max_entries: usize,
+    hits: u64,
+    misses: u64,
+    /// Whether entries changed since the last snapshot
+    dirty: bool,
+}
+
+/// Cache entries taken while the cache is locked, to be written to disk
+/// after it is released
+pub struct CacheSnapshot {
+    path: PathBuf,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl MetadataCache {
+    pub fn load(config: &CacheConfig) -> Self {
+        let path = config.path();
+        let entries = match Self::read(&path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                log::warn!("Failed to read metadata cache {:?}: {:#}", path, e);
+                HashMap::new()
+            }
+        };
+
+        log::debug!(
+            "Loaded {} metadata cache entries from {:?}",
+            entries.len(),
+            path
+        );
+
+        Self {
+            path,
+            entries,
+            ttl: config.ttl,
+            negative_ttl: config.negative_ttl,
+            max_entries: config.max_entries,
+            hits: 0,
+            misses: 0,
+            dirty: false,
+        }
+    }
+
+    fn read(path: &Path) -> PipeBoomResult<HashMap<String, CacheEntry>> {
+        if !path.exists() {
+            return Ok(HashMap::new());
+        }
+
+        let file = serde_json::from_str::<CacheFile>(&fs::read_to_string(path)?)?;
+        if file.version != CACHE_VERSION {
+            log::info!("Discarding metadata cache with version {}", file.version);
+            return Ok(HashMap::new());
+        }
+
+        Ok(file.entries)
+    }
+
+    /// Returns the cached lookup for `identity`. `Some(None)` is a cached
+    /// negative result; `None` means the track has to be resolved.
+    pub fn get(&mut self, identity: &str) -> Option<Option<SongDetails>> {
+        let now = current_time_as_u64().unwrap_or_default();
+
+        match self.entries.get_mut(identity) {
+            Some(entry) if !Self::is_expired(entry, now, self.ttl, self.negative_ttl) => {
+                entry.last_used = now;
+                self.hits += 1;
+                Some(entry.details.clone())
+            }
+            _ => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Returns cached details for `identity` even if they have expired, for
+    /// use when lookups aren't possible
+    pub fn get_stale(&self, identity: &str) -> Option<SongDetails> {
+        self.entries
+            .get(identity)
+            .and_then(|entry| entry.details.clone())
+    }
+
+    pub fn insert(&mut self, identity: String, details: Option<SongDetails>) {
+        let now = current_time_as_u64().unwrap_or_default();
+
+        self.entries.insert(
+            identity,
+            CacheEntry {
+                details,
+                stored_at: now,
+                last_used: now,
+            },
+        );
+        self.evict(now);
+        self.dirty = true;
+    }
+
+    pub fn clear(&mut self) -> PipeBoomResult<()> {
+        self.entries.clear();
+        self.hits = 0;
+        self.misses = 0;
+        self.dirty = false;
+
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let now = current_time_as_u64().unwrap_or_default();
+
+        CacheStats {
+            path: self.path.clone(),
+            entries: self.entries.len(),
+            negative_entries: self
+                .entries
+                .values()
+                .filter(|e| e.details.is_none())
+                .count(),
+            expired_entries: self
+                .entries
+                .values()
+                .filter(|e| Self::is_expired(e, now, self.ttl, self.negative_ttl))
+                .count(),
+            max_entries: self.max_entries,
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+
+    pub fn export(&self) -> HashMap<String, CacheEntry> {
+        self.entries.clone()
+    }
+
+    fn is_expired(entry: &CacheEntry, now: u64, ttl: u64, negative_ttl: u64) -> bool {
+        let ttl = if entry.details.is_some() {
+            ttl
+        } else {
+            negative_ttl
+        };
+
+        now.saturating_sub(entry.stored_at) > ttl
+    }
+
+    /// Once the cache outgrows `max_entries`, drops expired entries and then
+    /// the least recently used ones. Expired entries are kept until then, to
+    /// show while offline and to look the tracks up again by ID.
+    fn evict(&mut self, now: u64) {
+        if self.entries.len() <= self.max_entries {
+            return;
+        }
+
+        let (ttl, negative_ttl) = (self.ttl, self.negative_ttl);
+        let mut by_use = self
+            .entries
+            .iter()
+            .map(|(key, entry)| {
+                let fresh = !Self::is_expired(entry, now, ttl, negative_ttl);
+                (fresh, entry.last_used, key.clone())
+            })
+            .collect::<Vec<_>>();
+        by_use.sort();
+
+        let excess = self.entries.len() - self.max_entries;
+        for (_, _, key) in by_use.into_iter().take(excess) {
+            self.entries.remove(&key);
+        }
+
+        log::debug!("Evicted {} metadata cache entries", excess);
+    }
+
+    /// Returns the entries to write to disk if they changed since the last
+    /// snapshot
+    pub fn snapshot(&mut self) -> Option<CacheSnapshot> {
+        if !self.dirty {
+            return None;
+        }
+
+        self.dirty = false;
+        Some(CacheSnapshot {
+            path: self.path.clone(),
+            entries: self.entries.clone(),
+        })
+    }
+}
+
+impl CacheSnapshot {
+    pub fn write(&self) -> PipeBoomResult<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let file = serde_json::json!({
+            "version": CACHE_VERSION,
+            "entries": &self.entries,
+        });
+
+        // Write to a temporary file first so a crash never leaves a torn cache
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serde_json::to_vec(&file)?)?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Writes the cache to disk off the async runtime if it changed, keeping it
+/// marked as changed when that fails so the next flush retries
+pub async fn flush(cache: &Arc<Mutex<MetadataCache>>) {
+    let Some(snapshot) = cache.lock().unwrap().snapshot() else {
+        return;
+    };
+    let path = snapshot.path.clone();
+
+    let error = match tokio::task::spawn_blocking(move || snapshot.write()).await {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(e) => e.to_string(),
+    };
+
+    log::warn!("Failed to write metadata cache {:?}: {}", path, error);
+    cache.lock().unwrap().dirty = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::utils::temp_path;
+
+    fn cache(name: &str, max_entries: usize) -> MetadataCache {
+        let path = temp_path(&format!("cache-{}.json", name));
+
+        MetadataCache::load(&CacheConfig {
+            path: Some(path),
+            ttl: 100,
+            negative_ttl: 10,
+            max_entries,
+        })
+    }
+
+    fn details(url: &str) -> Option<SongDetails> {
+        Some(SongDetails::new(
+            "art".to_string(),
+            url.to_string(),
+            url.to_string(),
+        ))
+    }
+
+    #[test]
+    fn writes_snapshots_only_after_changes() {
+        let mut cache = cache("snapshot", 10);
+        assert!(cache.snapshot().is_none());
+
+        cache.insert("a".to_string(), details("https://a"));
+        cache.insert("b".to_string(), None);
+        let snapshot = cache.snapshot().unwrap();
+        assert!(cache.snapshot().is_none());
+        snapshot.write().unwrap();
+
+        let mut reloaded = MetadataCache::load(&CacheConfig {
+            path: Some(cache.path.clone()),
+            ..Default::default()
+        });
+        assert_eq!(
+            reloaded.get("a").flatten().map(|d| d.song_url),
+            Some("https://a".to_string())
+        );
+        assert!(matches!(reloaded.get("b"), Some(None)));
+        assert!(!cache.path.with_extension("json.tmp").exists());
+
+        cache.clear().unwrap();
+        assert!(!cache.path.exists());
+    }
+
+    #[test]
+    fn evicts_expired_then_least_recently_used() {
+        let mut cache = cache("evict", 2);
+        let now = current_time_as_u64().unwrap();
+        let entry = |stored_at, last_used| CacheEntry {
+            details: details("https://x"),
+            stored_at,
+            last_used,
+        };
+
+        cache
+            .entries
+            .insert("expired".to_string(), entry(now - 1000, now));
+        cache
+            .entries
+            .insert("old".to_string(), entry(now - 10, now - 50));
+        cache.entries.insert("new".to_string(), entry(now, now));
+        cache.evict(now);
+
+        let mut keys = cache.entries.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["new", "old"]);
+        assert_eq!(
+            cache.get_stale("old").map(|d| d.song_url),
+            Some("https://x".to_string())
+        );
+
+        cache.insert("newest".to_string(), None);
+        let mut keys = cache.entries.keys().cloned().collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, ["new", "newest"]);
+    }
+
+    #[test]
+    fn keeps_expired_entries_below_the_limit() {
+        let mut cache = cache("stale", 10);
+        let now = current_time_as_u64().unwrap();
+        cache.entries.insert(
+            "expired".to_string(),
+            CacheEntry {
+                details: details("https://old"),
+                stored_at: now - 1000,
+                last_used: now - 1000,
+            },
+        );
+        cache.insert("fresh".to_string(), None);
+
+        assert!(cache.get("expired").is_none());
+        assert_eq!(
+            cache.get_stale("expired").map(|d| d.song_url),
+            Some("https://old".to_string())
+        );
+    }
+}
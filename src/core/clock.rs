@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+/// Reported positions further than this from the predicted one count as a seek
+const SEEK_TOLERANCE: Duration = Duration::from_secs(2);
+
+/// Extrapolates the playback position between polls and notices seeks
+#[derive(Debug, Default)]
+pub struct PlaybackClock {
+    /// When the position was last reported, and what it was
+    anchor: Option<(Instant, Duration)>,
+    playing: bool,
+}
+
+impl PlaybackClock {
+    /// Records the position reported by the player. Returns true if it
+    /// doesn't match the extrapolated position, i.e. the user seeked.
+    pub fn update(&mut self, position: Duration, playing: bool) -> bool {
+        let seeked = self.position().is_some_and(|predicted| {
+            self.playing && playing && predicted.abs_diff(position) > SEEK_TOLERANCE
+        });
+
+        self.anchor = Some((Instant::now(), position));
+        self.playing = playing;
+
+        seeked
+    }
+
+    /// Current playback position, extrapolated from the last report
+    pub fn position(&self) -> Option<Duration> {
+        self.anchor.map(|(reported_at, position)| {
+            if self.playing {
+                position + reported_at.elapsed()
+            } else {
+                position
+            }
+        })
+    }
+
+    /// Freezes or restarts the extrapolation when the player pauses or
+    /// resumes without reporting a position
+    pub fn set_playing(&mut self, playing: bool) {
+        if let Some(position) = self.position() {
+            self.anchor = Some((Instant::now(), position));
+        }
+        self.playing = playing;
+    }
+
+    pub fn reset(&mut self) {
+        self.anchor = None;
+        self.playing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAST: Duration = Duration::from_secs(60);
+
+    /// Clock whose last report is `ago` in the past
+    fn reported(position: Duration, playing: bool, ago: Duration) -> PlaybackClock {
+        PlaybackClock {
+            anchor: Some((Instant::now() - ago, position)),
+            playing,
+        }
+    }
+
+    #[test]
+    fn detects_seeks() {
+        let position = Duration::from_secs(100);
+
+        // (reported, playing now, expected seek)
+        let cases = [
+            (position + PAST, true, false),
+            (position + PAST + SEEK_TOLERANCE * 2, true, true),
+            (position, true, true),
+            (position, false, false),
+        ];
+
+        for (reported_position, playing, expected) in cases {
+            let mut clock = reported(position, true, PAST);
+            assert_eq!(
+                clock.update(reported_position, playing),
+                expected,
+                "{:?} {}",
+                reported_position,
+                playing
+            );
+        }
+    }
+
+    #[test]
+    fn resuming_after_a_pause_is_not_a_seek() {
+        let position = Duration::from_secs(100);
+        let mut clock = reported(position, true, Duration::ZERO);
+
+        clock.set_playing(false);
+        clock.anchor = clock.anchor.map(|(at, position)| (at - PAST, position));
+        assert!(clock.position().unwrap() < position + SEEK_TOLERANCE);
+
+        clock.set_playing(true);
+        assert!(!clock.update(position, true));
+    }
+
+    #[test]
+    fn seeking_while_paused_is_noticed_on_resume() {
+        let mut clock = reported(Duration::from_secs(100), true, Duration::ZERO);
+
+        clock.set_playing(false);
+        clock.set_playing(true);
+        assert!(clock.update(Duration::from_secs(10), true));
+    }
+}
@@ -0,0 +1,539 @@
+use std::{
+    collections::HashMap,
+    env::{home_dir, temp_dir},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::core::{
+    constants::BUNDLE_ID,
+    error::{PipeBoomError, PipeBoomResult},
+    utils::system_locale,
+};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Never make external requests. Only cached metadata and placeholder
+    /// artwork are shown.
+    pub offline: bool,
+    pub presence: PresenceConfig,
+    pub lookup: LookupConfig,
+    pub artwork: ArtworkConfig,
+    pub cache: CacheConfig,
+    pub http: HttpConfig,
+    pub lyrics: LyricsConfig,
+    pub apple_music: AppleMusicConfig,
+    pub overrides: OverridesConfig,
+    pub polling: PollingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PresenceConfig {
+    /// What to show as the activity's party size, e.g. "(3 of 12)"
+    pub party: PartyMode,
+    /// Activity text templates. A state that renders empty falls back to
+    /// the artist.
+    pub details: String,
+    pub state: String,
+    /// Activity buttons. Buttons whose URL renders empty are left out.
+    pub buttons: Vec<ButtonTemplate>,
+    /// Seconds to keep showing the last track after playback stops or the
+    /// app closes. 0 clears the activity right away.
+    pub last_listened: u64,
+    /// Templates for the last track, shown with the time since it stopped
+    pub last_listened_details: String,
+    pub last_listened_state: String,
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        Self {
+            party: PartyMode::default(),
+            details: "{title}".to_string(),
+            state: "{artist}".to_string(),
+            buttons: vec![
+                ButtonTemplate {
+                    label: "Listen on Apple Music".to_string(),
+                    url: "{song_url}".to_string(),
+                },
+                ButtonTemplate {
+                    label: "Share your AM status too!".to_string(),
+                    url: "https://shadhaan.me/api/projects/pipeboom".to_string(),
+                },
+            ],
+            last_listened: 0,
+            last_listened_details: "Last listened to {title}".to_string(),
+            last_listened_state: "by {artist}".to_string(),
+        }
+    }
+}
+
+/// Activity button whose label and URL may contain `{placeholders}`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ButtonTemplate {
+    pub label: String,
+    pub url: String,
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PartyMode {
+    #[default]
+    Off,
+    /// Track number within the album
+    Album,
+    /// Track position within the current playlist
+    Playlist,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LookupConfig {
+    /// Two-letter storefront country code, or "auto" to use the system region
+    pub country: String,
+    /// Storefront language such as "ja_jp", or "auto" to use the system locale
+    pub language: String,
+    /// Storefront to retry in when the primary one returns nothing
+    pub fallback_country: Option<String>,
+    /// Metadata providers, tried in order until one has artwork and links.
+    /// Only iTunes by default; MusicBrainz, Deezer and Apple Music are opt-in.
+    pub providers: Vec<ProviderKind>,
+    /// Per-provider timeouts in milliseconds
+    pub timeouts: HashMap<ProviderKind, u64>,
+    /// Timeout in milliseconds for providers without their own
+    pub default_timeout: u64,
+    pub endpoints: EndpointsConfig,
+    pub circuit: CircuitConfig,
+    /// Resolve Spotify, YouTube and song.link URLs with Odesli
+    pub share_links: bool,
+}
+
+impl Default for LookupConfig {
+    fn default() -> Self {
+        Self {
+            country: "auto".to_string(),
+            language: "auto".to_string(),
+            fallback_country: Some("us".to_string()),
+            providers: vec![ProviderKind::ItunesLookup, ProviderKind::ItunesSearch],
+            timeouts: HashMap::new(),
+            default_timeout: 5000,
+            endpoints: EndpointsConfig::default(),
+            circuit: CircuitConfig::default(),
+            share_links: false,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    ItunesLookup,
+    ItunesSearch,
+    #[serde(rename = "musicbrainz")]
+    MusicBrainz,
+    Deezer,
+    /// Apple Music catalog API, needs credentials in `[apple_music]`
+    AppleMusic,
+}
+
+/// Base URLs of external APIs, overridable to point at local stubs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct EndpointsConfig {
+    pub itunes: String,
+    pub musicbrainz: String,
+    pub cover_art_archive: String,
+    pub deezer: String,
+    pub odesli: String,
+    pub lrclib: String,
+    pub apple_music: String,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            itunes: "https://itunes.apple.com".to_string(),
+            musicbrainz: "https://musicbrainz.org".to_string(),
+            cover_art_archive: "https://coverartarchive.org".to_string(),
+            deezer: "https://api.deezer.com".to_string(),
+            odesli: "https://api.song.link".to_string(),
+            lrclib: "https://lrclib.net".to_string(),
+            apple_music: "https://api.music.apple.com".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CircuitConfig {
+    /// Consecutive failures before a provider is skipped
+    pub failure_threshold: u32,
+    /// Seconds a provider is skipped for, doubled after each failed retry
+    pub cooldown: u64,
+    /// Upper bound for the cooldown in seconds
+    pub max_cooldown: u64,
+}
+
+impl Default for CircuitConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            cooldown: 60,
+            max_cooldown: 15 * 60,
+        }
+    }
+}
+
+impl LookupConfig {
+    pub fn timeout(&self, provider: ProviderKind) -> Duration {
+        Duration::from_millis(
+            self.timeouts
+                .get(&provider)
+                .copied()
+                .unwrap_or(self.default_timeout),
+        )
+    }
+
+    /// Resolves "auto" values against the system locale
+    pub fn storefront(&self) -> Storefront {
+        let locale = if self.country == "auto" || self.language == "auto" {
+            system_locale()
+        } else {
+            None
+        };
+
+        let country = if self.country == "auto" {
+            locale
+                .as_ref()
+                .and_then(|(_, country)| country.clone())
+                .unwrap_or_else(|| "us".to_string())
+        } else {
+            self.country.to_lowercase()
+        };
+
+        let language = if self.language == "auto" {
+            locale.map(|(language, _)| format!("{}_{}", language, country))
+        } else {
+            Some(self.language.to_lowercase())
+        };
+
+        let fallback_country = self
+            .fallback_country
+            .as_ref()
+            .map(|c| c.to_lowercase())
+            .filter(|c| *c != country);
+
+        Storefront {
+            country,
+            language,
+            fallback_country,
+        }
+    }
+}
+
+/// The Apple Music storefront that lookups and links are made against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Storefront {
+    pub country: String,
+    pub language: Option<String>,
+    pub fallback_country: Option<String>,
+}
+
+impl Storefront {
+    pub fn home_url(&self) -> String {
+        format!("https://music.apple.com/{}/", self.country)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ArtworkConfig {
+    /// Requested artwork width and height in pixels
+    pub size: u32,
+    pub fallback: FallbackConfig,
+}
+
+impl Default for ArtworkConfig {
+    fn default() -> Self {
+        Self {
+            size: 512,
+            fallback: FallbackConfig::default(),
+        }
+    }
+}
+
+/// Images shown when no provider has artwork, as Discord asset keys or URLs
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FallbackConfig {
+    /// Images by the track's genre, matched case-insensitively
+    pub genres: HashMap<String, String>,
+    /// Images picked from by a hash of the album, so each album keeps its own
+    pub palette: Vec<String>,
+    /// Image used when neither of the above applies, instead of "no_art"
+    pub default_image: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// Cache file location, defaults to `~/Library/Caches/<bundle id>/metadata.json`
+    pub path: Option<PathBuf>,
+    /// Lifetime of resolved metadata in seconds
+    pub ttl: u64,
+    /// Lifetime of "no match" results in seconds
+    pub negative_ttl: u64,
+    /// Maximum number of cached tracks before the least recently used are evicted
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            ttl: 30 * 24 * 60 * 60,
+            negative_ttl: 24 * 60 * 60,
+            max_entries: 5000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OverridesConfig {
+    /// Override rules in TOML, or JSON with a `.json` extension. Defaults to
+    /// `~/.config/pipeboom/overrides.toml`
+    pub path: Option<PathBuf>,
+}
+
+impl OverridesConfig {
+    pub fn path(&self) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| {
+            home_dir()
+                .unwrap_or(temp_dir())
+                .join(".config/pipeboom/overrides.toml")
+        })
+    }
+}
+
+impl CacheConfig {
+    pub fn path(&self) -> PathBuf {
+        self.path.clone().unwrap_or_else(|| {
+            home_dir()
+                .unwrap_or(temp_dir())
+                .join("Library/Caches")
+                .join(BUNDLE_ID)
+                .join("metadata.json")
+        })
+    }
+}
+
+/// Credentials for the Apple Music catalog API: either a developer token, or
+/// a MusicKit `.p8` key to sign one with
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AppleMusicConfig {
+    pub developer_token: Option<String>,
+    pub key_path: Option<PathBuf>,
+    pub key_id: Option<String>,
+    pub team_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LyricsConfig {
+    /// Look up synced lyrics and expose the current line as `{lyric}`
+    pub enabled: bool,
+    /// Read `.lrc` files next to local tracks before asking LRCLIB
+    pub local_files: bool,
+    /// Lines shown for less than this many milliseconds are skipped, keeping
+    /// presence updates within Discord's rate limit
+    pub min_line_duration: u64,
+}
+
+impl Default for LyricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            local_files: true,
+            min_line_duration: 4000,
+        }
+    }
+}
+
+/// Bounds for the adaptive poll interval, in milliseconds. `--poll-interval`
+/// is used while a track is playing.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PollingConfig {
+    /// Used near the end of a track, so the next one shows up quickly
+    pub min_interval: u64,
+    /// Used while the music app isn't running or polling is suspended
+    pub max_interval: u64,
+    /// Seconds without playback before the activity is cleared and polling
+    /// is suspended. 0 never suspends.
+    pub idle_timeout: u64,
+}
+
+impl PollingConfig {
+    /// Rejects a `--poll-interval` outside `min_interval` and `max_interval`,
+    /// rather than silently polling at a different rate than asked
+    pub fn check_interval(&self, interval: Duration) -> PipeBoomResult<()> {
+        let min_interval = Duration::from_millis(self.min_interval);
+        let max_interval = Duration::from_millis(self.max_interval);
+        if interval < min_interval {
+            return Err(PipeBoomError::config(format!(
+                "Poll interval {:?} is below polling.min_interval ({:?})",
+                interval, min_interval
+            )));
+        }
+        if interval > max_interval {
+            return Err(PipeBoomError::config(format!(
+                "Poll interval {:?} is above polling.max_interval ({:?})",
+                interval, max_interval
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> PipeBoomResult<()> {
+        if self.min_interval > self.max_interval {
+            return Err(PipeBoomError::config(format!(
+                "polling.min_interval ({}ms) is above polling.max_interval ({}ms)",
+                self.min_interval, self.max_interval
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for PollingConfig {
+    fn default() -> Self {
+        Self {
+            min_interval: 250,
+            max_interval: 30000,
+            idle_timeout: 30 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// Proxy URL, e.g. "http://proxy:8080" or "socks5h://localhost:1080"
+    pub proxy: Option<String>,
+    /// Sent with every request. MusicBrainz asks every client to identify itself
+    pub user_agent: String,
+    /// Total request timeout in milliseconds
+    pub timeout: u64,
+    /// Connection timeout in milliseconds
+    pub connect_timeout: u64,
+    /// Record responses into `fixtures_dir`, or replay them without network access
+    pub fixtures: FixtureMode,
+    pub fixtures_dir: Option<PathBuf>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            proxy: None,
+            user_agent: concat!(
+                "PipeBoom/",
+                env!("CARGO_PKG_VERSION"),
+                " ( https://github.com/byytelope/pipeboom )"
+            )
+            .to_string(),
+            timeout: 10_000,
+            connect_timeout: 5_000,
+            fixtures: FixtureMode::Off,
+            fixtures_dir: None,
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn fixtures_dir(&self) -> PathBuf {
+        self.fixtures_dir.clone().unwrap_or_else(|| {
+            home_dir()
+                .unwrap_or(temp_dir())
+                .join(".config/pipeboom/fixtures")
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FixtureMode {
+    #[default]
+    Off,
+    /// Save every response as a fixture file
+    Record,
+    /// Serve responses from fixture files and fail requests without one
+    Replay,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> PipeBoomResult<Self> {
+        if !path.exists() {
+            log::debug!("No config file at {:?}, using defaults", path);
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(path)?;
+        Self::parse(&contents).map_err(|e| e.context(format!("Invalid config file {:?}", path)))
+    }
+
+    fn parse(contents: &str) -> PipeBoomResult<Self> {
+        let config = toml::from_str::<Self>(contents)
+            .map_err(|e| PipeBoomError::config("Failed to parse config").with_source(e))?;
+        config.polling.validate()?;
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_poll_intervals() {
+        let polling = PollingConfig::default();
+
+        let cases = [
+            // (interval ms, accepted)
+            (100, false),
+            (250, true),
+            (1500, true),
+            (30000, true),
+            (30001, false),
+        ];
+
+        for (interval, accepted) in cases {
+            let checked = polling.check_interval(Duration::from_millis(interval));
+            assert_eq!(checked.is_ok(), accepted, "{}", interval);
+        }
+    }
+
+    #[test]
+    fn rejects_inverted_polling_bounds() {
+        let cases = [
+            // (config, accepted)
+            ("", true),
+            ("[polling]\nmin_interval = 1000\nmax_interval = 1000", true),
+            ("[polling]\nmin_interval = 5000\nmax_interval = 1000", false),
+            ("[polling]\nmax_interval = 100", false),
+        ];
+
+        for (contents, accepted) in cases {
+            assert_eq!(Config::parse(contents).is_ok(), accepted, "{:?}", contents);
+        }
+    }
+}
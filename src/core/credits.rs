@@ -0,0 +1,263 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+/// Featuring markers in English and the other languages Apple Music
+/// storefronts commonly credit with, e.g. "con" (es, it), "avec" (fr),
+/// "mit" (de) and "com"/"part." (pt)
+const FEATURING_MARKER: &str = r"(?:feat\.?|ft\.?|featuring|with|con|avec|mit|com|part\.)";
+
+/// `(feat. X)`, `[con X]` and similar bracketed featuring clauses
+static FEATURING_GROUP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(
+        r"(?i)\s*[(\[]{}\s+([^)\]]+)[)\]]",
+        FEATURING_MARKER
+    ))
+    .unwrap()
+});
+
+/// Unbracketed trailing featuring clauses, e.g. `Song feat. X`. Only the
+/// abbreviations count here, so titles like "Bailando con Lobos" stay whole.
+static FEATURING_TRAILING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\s+(?:feat\.?|ft\.?|featuring)\s+(.+)$").unwrap());
+
+/// Bracketed edition markers, e.g. `[2011 Remaster]` or `(Deluxe Edition)`
+static EDITION_GROUP: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\s*[(\[][^)\]]*\b(?:remaster(?:ed)?|deluxe|expanded|anniversary|edition|version|mono|stereo|bonus track|radio edit|explicit|clean)\b[^)\]]*[)\]]",
+    )
+    .unwrap()
+});
+
+/// Dash-separated edition suffixes, e.g. `- Single Version` or `- 2011 Remaster`
+static EDITION_SUFFIX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)\s+[-–—]\s+[^-–—]*\b(?:remaster(?:ed)?|version|radio edit|mono|stereo|deluxe|edition)\b[^-–—]*$",
+    )
+    .unwrap()
+});
+
+/// Explicit featuring markers between artists in a credit. Commas,
+/// ampersands and "and" are left alone, as in "Earth, Wind & Fire".
+static ARTIST_SEPARATOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!(r"(?i)\s+{}\s+", FEATURING_MARKER)).unwrap());
+
+/// Separators between the artists of a featuring clause, e.g. "X & Y",
+/// "X、Y" or "X y Z". Never applied to the primary artist.
+static FEATURED_SEPARATOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\s*(?:,|&|、|×)\s*|\s+(?:and|y|et|und)\s+").unwrap());
+
+/// Structured view of a song's artist credit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credits {
+    pub primary_artist: String,
+    pub featured_artists: Vec<String>,
+}
+
+impl Credits {
+    /// Splits `artist` into its primary and featured artists, also picking up
+    /// featuring clauses that only appear in `title`
+    pub fn parse(artist: &str, title: &str) -> Self {
+        let artist = fold_width(artist);
+        let mut clauses = ARTIST_SEPARATOR.split(&artist);
+        let primary_artist = match clauses.next().map(str::trim) {
+            Some(primary) if !primary.is_empty() => primary.to_string(),
+            _ => artist.trim().to_string(),
+        };
+
+        let title = fold_width(title);
+        let title_clauses = FEATURING_GROUP
+            .captures_iter(&title)
+            .chain(FEATURING_TRAILING.captures_iter(&title))
+            .map(|captures| captures.get(1).map_or("", |m| m.as_str()));
+
+        let mut featured_artists: Vec<String> = Vec::new();
+        for clause in clauses.chain(title_clauses) {
+            for featured in split_featured(clause) {
+                let known = featured.eq_ignore_ascii_case(&primary_artist)
+                    || featured_artists
+                        .iter()
+                        .any(|a| a.eq_ignore_ascii_case(&featured));
+                if !known {
+                    featured_artists.push(featured);
+                }
+            }
+        }
+
+        Self {
+            primary_artist,
+            featured_artists,
+        }
+    }
+}
+
+/// Strips featuring clauses and edition suffixes from a song or album title
+pub fn clean_title(title: &str) -> String {
+    let original = fold_width(title);
+    let title = FEATURING_GROUP.replace_all(&original, "");
+    let title = EDITION_GROUP.replace_all(&title, "");
+    let title = FEATURING_TRAILING.replace(&title, "");
+    let title = EDITION_SUFFIX.replace(&title, "");
+
+    let cleaned = title.trim();
+    if cleaned.is_empty() {
+        // Never reduce a title to nothing, e.g. "(Remastered)"
+        return original.trim().to_string();
+    }
+
+    cleaned.to_string()
+}
+
+/// Folds full-width forms to their ASCII equivalents and removes diacritics,
+/// so "Ｂｅｙｏｎｃé" and "Beyonce" compare equal
+pub fn fold(text: &str) -> String {
+    text.nfkd()
+        .filter(|c| !('\u{0300}'..='\u{036F}').contains(c))
+        .nfc()
+        .collect()
+}
+
+/// Folds full-width and other compatibility forms only, keeping diacritics
+fn fold_width(text: &str) -> String {
+    text.nfkc().collect()
+}
+
+/// Splits a featuring clause into its artists, including nested markers as in
+/// "X & Y with Z"
+fn split_featured(clause: &str) -> Vec<String> {
+    ARTIST_SEPARATOR
+        .split(clause)
+        .flat_map(|part| FEATURED_SEPARATOR.split(part))
+        .map(str::trim)
+        .filter(|artist| !artist.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_credits() {
+        let cases = [
+            // (artist, title, primary, featured)
+            (
+                "Simon & Garfunkel",
+                "The Boxer",
+                "Simon & Garfunkel",
+                vec![],
+            ),
+            (
+                "Earth, Wind & Fire",
+                "September",
+                "Earth, Wind & Fire",
+                vec![],
+            ),
+            (
+                "Crosby, Stills, Nash & Young",
+                "Ohio",
+                "Crosby, Stills, Nash & Young",
+                vec![],
+            ),
+            ("Years & Years", "King", "Years & Years", vec![]),
+            (
+                "Calvin Harris feat. Rihanna",
+                "This Is What You Came For",
+                "Calvin Harris",
+                vec!["Rihanna"],
+            ),
+            (
+                "Drake ft. Wizkid & Kyla",
+                "One Dance",
+                "Drake",
+                vec!["Wizkid", "Kyla"],
+            ),
+            (
+                "Simon & Garfunkel feat. A, B and C",
+                "Song",
+                "Simon & Garfunkel",
+                vec!["A", "B", "C"],
+            ),
+            (
+                "Rosalía con J Balvin y El Guincho",
+                "Song",
+                "Rosalía",
+                vec!["J Balvin", "El Guincho"],
+            ),
+            (
+                "Angèle avec Roméo Elvis",
+                "Song",
+                "Angèle",
+                vec!["Roméo Elvis"],
+            ),
+            (
+                "Peter Fox mit Cold Steel",
+                "Song",
+                "Peter Fox",
+                vec!["Cold Steel"],
+            ),
+            (
+                "Anitta part. Pabllo Vittar",
+                "Song",
+                "Anitta",
+                vec!["Pabllo Vittar"],
+            ),
+            (
+                "米津玄師",
+                "Song (feat. 菅田将暉、野田洋次郎)",
+                "米津玄師",
+                vec!["菅田将暉", "野田洋次郎"],
+            ),
+            ("Ａｄｏ ｆｅａｔ． Ａ ＆ Ｂ", "Song", "Ado", vec!["A", "B"]),
+            ("Bad Bunny", "Song (con Drake)", "Bad Bunny", vec!["Drake"]),
+            ("Los Lobos", "Bailando con Lobos", "Los Lobos", vec![]),
+            ("A x B with C", "Song", "A x B", vec!["C"]),
+            (
+                "Mark Ronson",
+                "Uptown Funk (feat. Bruno Mars)",
+                "Mark Ronson",
+                vec!["Bruno Mars"],
+            ),
+            (
+                "Mark Ronson feat. Bruno Mars",
+                "Uptown Funk (feat. Bruno Mars)",
+                "Mark Ronson",
+                vec!["Bruno Mars"],
+            ),
+            ("Ｂｅｙｏｎｃé", "Halo", "Beyoncé", vec![]),
+            ("Without", "Song", "Without", vec![]),
+        ];
+
+        for (artist, title, primary, featured) in cases {
+            let credits = Credits::parse(artist, title);
+            assert_eq!(credits.primary_artist, primary, "{:?}", artist);
+            assert_eq!(credits.featured_artists, featured, "{:?}", artist);
+        }
+    }
+
+    #[test]
+    fn cleans_titles() {
+        let cases = [
+            // (title, expected)
+            ("Song (feat. X) [2011 Remaster] - Single Version", "Song"),
+            ("Dreams - 2004 Remaster", "Dreams"),
+            ("Rumours (Super Deluxe)", "Rumours"),
+            ("Uptown Funk feat. Bruno Mars", "Uptown Funk"),
+            ("Hotel California (Live)", "Hotel California (Live)"),
+            ("(Remastered)", "(Remastered)"),
+            ("Ｈｅｌｌｏ", "Hello"),
+        ];
+
+        for (title, expected) in cases {
+            assert_eq!(clean_title(title), expected, "{:?}", title);
+        }
+    }
+
+    #[test]
+    fn folds_text() {
+        assert_eq!(fold("Ｂｅｙｏｎｃé"), "Beyonce");
+        assert_eq!(fold("Sigur Rós"), "Sigur Ros");
+    }
+}
@@ -1,97 +1,315 @@
-use std::fmt;
+use std::{error::Error, fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
 
 pub type PipeBoomResult<T> = std::result::Result<T, PipeBoomError>;
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub enum PipeBoomError {
-    /// Errors due to the Apple Music app
-    AppleMusic(String),
-    /// Errors from Discord Rich Presence
-    Discord(String),
-    /// Errors from the Osascript interface
-    Osascript(String),
-    /// Errors in configuration
-    Config(String),
-    /// Errors in parsing data
-    Parse(String),
-    /// IO errors
-    Io(String),
-    /// Network errors
-    Network(String),
-    /// General internal errors
-    Internal(String),
-    /// Errors in IPC implementation
-    Ipc(String),
-    /// Errors during setup/uninstallation
-    Setup(String),
-}
+type Source = Box<dyn Error + Send + Sync + 'static>;
 
-impl fmt::Display for PipeBoomError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            PipeBoomError::AppleMusic(msg) => write!(f, "APPLE MUSIC ERROR: {}", msg),
-            PipeBoomError::Discord(msg) => write!(f, "DISCORD ERROR: {}", msg),
-            PipeBoomError::Osascript(msg) => write!(f, "OSASCRIPT ERROR: {}", msg),
-            PipeBoomError::Config(msg) => write!(f, "CONFIGURATION ERROR: {}", msg),
-            PipeBoomError::Parse(msg) => write!(f, "PARSE ERROR: {}", msg),
-            PipeBoomError::Io(msg) => write!(f, "IO ERROR: {}", msg),
-            PipeBoomError::Network(msg) => write!(f, "NETWORK ERROR: {}", msg),
-            PipeBoomError::Internal(msg) => write!(f, "INTERNAL ERROR: {}", msg),
-            PipeBoomError::Ipc(msg) => {
-                write!(f, "IPC ERROR: {}", msg)
-            }
-            PipeBoomError::Setup(msg) => {
-                write!(f, "SETUP ERROR: {}", msg)
+/// Declares [`ErrorCode`] with each code string written once, used both by
+/// serde over IPC and by [`ErrorCode::as_str`]
+macro_rules! error_codes {
+    ($($(#[$meta:meta])* $variant:ident = $code:literal,)*) => {
+        /// Stable, machine-readable identifier of an error condition. Clients
+        /// may rely on these over IPC, so existing codes must not change.
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        pub enum ErrorCode {
+            $(
+                $(#[$meta])*
+                #[serde(rename = $code)]
+                $variant,
+            )*
+        }
+
+        impl ErrorCode {
+            /// Every code, in declaration order
+            pub const ALL: &[ErrorCode] = &[$(ErrorCode::$variant),*];
+
+            pub fn as_str(self) -> &'static str {
+                match self {
+                    $(ErrorCode::$variant => $code,)*
+                }
             }
         }
-    }
+    };
 }
 
-impl PipeBoomError {
-    pub fn is_recoverable(&self) -> bool {
+error_codes! {
+    /// Discord isn't running
+    DiscordNotRunning = "discord.not_running",
+    /// Talking to Discord over its IPC socket failed
+    DiscordIpc = "discord.ipc",
+    /// The music app isn't running
+    MusicNotRunning = "music.not_running",
+    /// macOS didn't allow PipeBoom to control the music app
+    MusicPermissionDenied = "music.permission_denied",
+    /// An Osascript call to the music app failed
+    MusicScriptFailed = "music.script_failed",
+    /// No track is playing or it hasn't been resolved yet
+    PlayerNoTrack = "player.no_track",
+    /// The player controller isn't running
+    PlayerUnavailable = "player.unavailable",
+    /// A metadata, link or lyrics API couldn't be reached or returned an error
+    LookupNetwork = "lookup.network",
+    /// The remote API is throttling requests
+    LookupRateLimited = "lookup.rate_limited",
+    /// The config file, overrides file or a command-line option is invalid
+    ConfigInvalid = "config.invalid",
+    /// Data couldn't be parsed
+    DataInvalid = "data.invalid",
+    /// Reading or writing a local file failed
+    IoFailed = "io.failed",
+    /// The service's socket is missing or not accepting connections
+    IpcUnavailable = "ipc.unavailable",
+    /// An IPC message couldn't be sent, read or handled
+    IpcFailed = "ipc.failed",
+    /// Another service instance is running
+    InstanceAlreadyRunning = "instance.already_running",
+    /// Installing or removing the Launch Agent failed
+    SetupFailed = "setup.failed",
+    /// An unexpected condition, usually a bug
+    Internal = "internal",
+}
+
+impl ErrorCode {
+    /// Whether the same operation may succeed if tried again later without
+    /// the user changing anything
+    pub fn is_retryable(self) -> bool {
         matches!(
             self,
-            PipeBoomError::Discord(_) | PipeBoomError::AppleMusic(_) | PipeBoomError::Network(_)
+            ErrorCode::DiscordNotRunning
+                | ErrorCode::DiscordIpc
+                | ErrorCode::MusicNotRunning
+                | ErrorCode::PlayerNoTrack
+                | ErrorCode::LookupNetwork
+                | ErrorCode::LookupRateLimited
+                | ErrorCode::IpcUnavailable
         )
     }
 }
 
-impl std::error::Error for PipeBoomError {}
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
 
-macro_rules! impl_from_error {
-    ($error_type:ty => $variant:ident) => {
-        impl From<$error_type> for PipeBoomError {
-            fn from(err: $error_type) -> Self {
-                PipeBoomError::$variant(err.to_string())
+/// An error with a stable code, a message and the error that caused it, if
+/// any. Displays as just the message, which is how `source()` walkers expect
+/// it; the alternate form `{:#}` appends every cause, e.g. "Failed to bind
+/// Unix socket: Address in use".
+#[derive(Debug)]
+pub struct PipeBoomError {
+    code: ErrorCode,
+    message: String,
+    source: Option<Source>,
+    /// How long a rate-limited API asked to wait
+    retry_after: Option<Duration>,
+}
+
+impl PipeBoomError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            source: None,
+            retry_after: None,
+        }
+    }
+
+    pub fn discord(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::DiscordIpc, message)
+    }
+
+    pub fn apple_music(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::MusicScriptFailed, message)
+    }
+
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::ConfigInvalid, message)
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::DataInvalid, message)
+    }
+
+    pub fn io(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::IoFailed, message)
+    }
+
+    pub fn network(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::LookupNetwork, message)
+    }
+
+    /// The remote API is throttling requests, optionally saying for how long
+    pub fn rate_limited(retry_after: Option<Duration>) -> Self {
+        let message = match retry_after {
+            Some(retry_after) => format!("Rate limited, retry after {:?}", retry_after),
+            None => "Rate limited".to_string(),
+        };
+
+        Self {
+            retry_after,
+            ..Self::new(ErrorCode::LookupRateLimited, message)
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::Internal, message)
+    }
+
+    pub fn ipc(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::IpcFailed, message)
+    }
+
+    pub fn setup(message: impl Into<String>) -> Self {
+        Self::new(ErrorCode::SetupFailed, message)
+    }
+
+    pub fn with_source(mut self, source: impl Into<Source>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Wraps this error in one with `message`, keeping its code
+    pub fn context(self, message: impl Into<String>) -> Self {
+        Self {
+            code: self.code,
+            retry_after: self.retry_after,
+            message: message.into(),
+            source: Some(Box::new(self)),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        self.retry_after
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.code.is_retryable()
+    }
+}
+
+impl fmt::Display for PipeBoomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+
+        if f.alternate() {
+            let mut source = self.source();
+            while let Some(cause) = source {
+                write!(f, ": {}", cause)?;
+                source = cause.source();
             }
         }
-    };
-    ($error_type:ty => $variant:ident, $format:expr) => {
+
+        Ok(())
+    }
+}
+
+impl Error for PipeBoomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|s| s as &(dyn Error + 'static))
+    }
+}
+
+macro_rules! impl_from_error {
+    ($error_type:ty => $constructor:ident, $message:expr) => {
         impl From<$error_type> for PipeBoomError {
             fn from(err: $error_type) -> Self {
-                PipeBoomError::$variant(format!($format, err))
+                PipeBoomError::$constructor($message).with_source(err)
             }
         }
     };
 }
 
-impl_from_error!(std::num::ParseIntError => Parse);
-impl_from_error!(std::num::ParseFloatError => Parse);
-impl_from_error!(std::io::Error => Io);
-impl_from_error!(serde_json::Error => Parse);
-impl_from_error!(surf::Error => Network);
-impl_from_error!(discord_rich_presence::error::Error => Discord);
-impl_from_error!(Box<dyn std::error::Error> => Internal);
-impl_from_error!(std::time::SystemTimeError => Internal, "SYSTEM TIME ERROR: {}");
+impl_from_error!(std::num::ParseIntError => parse, "Invalid integer");
+impl_from_error!(std::num::ParseFloatError => parse, "Invalid number");
+impl_from_error!(std::io::Error => io, "IO error");
+impl_from_error!(serde_json::Error => parse, "Invalid JSON");
+impl_from_error!(surf::Error => network, "HTTP request failed");
+impl_from_error!(discord_rich_presence::error::Error => discord, "Discord IPC error");
+impl_from_error!(Source => internal, "Unexpected error");
+impl_from_error!(std::time::SystemTimeError => internal, "System time error");
 
 impl From<&str> for PipeBoomError {
     fn from(err: &str) -> Self {
-        PipeBoomError::Internal(err.to_string())
+        PipeBoomError::internal(err)
     }
 }
 
 impl From<String> for PipeBoomError {
     fn from(err: String) -> Self {
-        PipeBoomError::Internal(err)
+        PipeBoomError::internal(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip_through_serde() {
+        for code in ErrorCode::ALL {
+            let json = serde_json::to_value(code).unwrap();
+            assert_eq!(json, code.as_str());
+            assert_eq!(serde_json::from_value::<ErrorCode>(json).unwrap(), *code);
+        }
+    }
+
+    #[test]
+    fn conversions_keep_their_source() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        let boxed: Source = "boxed".into();
+
+        // (error, code, display with causes)
+        let cases = [
+            (
+                PipeBoomError::from(io),
+                ErrorCode::IoFailed,
+                "IO error: missing",
+            ),
+            (
+                PipeBoomError::from(surf::Error::from_str(500, "down")),
+                ErrorCode::LookupNetwork,
+                "HTTP request failed: down",
+            ),
+            (
+                PipeBoomError::from(discord_rich_presence::error::Error::IPCNotFound),
+                ErrorCode::DiscordIpc,
+                "Discord IPC error: failed to find IPC socket",
+            ),
+            (
+                PipeBoomError::from(boxed),
+                ErrorCode::Internal,
+                "Unexpected error: boxed",
+            ),
+        ];
+
+        for (error, code, display) in cases {
+            assert_eq!(error.code(), code);
+            assert_eq!(format!("{:#}", error), display);
+            assert!(error.source().is_some(), "{}", display);
+        }
+    }
+
+    #[test]
+    fn displays_each_cause_once() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "missing");
+        let error = PipeBoomError::from(io).context("Failed to load cache");
+
+        assert_eq!(error.to_string(), "Failed to load cache");
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to load cache: IO error: missing"
+        );
+
+        let causes = std::iter::successors(Some(&error as &dyn Error), |e| (*e).source())
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(causes, ["Failed to load cache", "IO error", "missing"]);
     }
 }
@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use crate::core::{
+    error::ErrorCode,
+    models::{PlayerState, Song, SongDetails},
+};
+
+/// Events buffered per subscriber before it starts missing them
+const EVENT_CAPACITY: usize = 256;
+
+/// Something the player controller did or noticed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TrackChanged {
+        song: Box<Song>,
+    },
+    PlaybackStateChanged {
+        state: PlayerState,
+    },
+    /// The player jumped to `position`, in seconds
+    Seeked {
+        position: f32,
+    },
+    MetadataResolved {
+        identity: String,
+        details: Box<SongDetails>,
+    },
+    /// The controller wants Discord to show `presence`. Only published when
+    /// it differs from the previous one.
+    PresenceChanged {
+        presence: Presence,
+    },
+    /// Discord accepted a presence for a playing track
+    PresenceSent {
+        title: String,
+        artist: String,
+        lyric: Option<String>,
+    },
+    DiscordConnected,
+    DiscordDisconnected,
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// What the Discord activity should show
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Presence {
+    /// A playing track, with its position in the album or playlist and the
+    /// lyric line being sung
+    Playing {
+        song: Box<Song>,
+        details: Box<SongDetails>,
+        party: Option<(u32, u32)>,
+        lyric: Option<String>,
+    },
+    /// The track played last, until some time after `stopped_at`, in seconds
+    /// since the epoch
+    LastListened {
+        song: Box<Song>,
+        details: Box<SongDetails>,
+        stopped_at: u64,
+    },
+    Cleared,
+}
+
+impl Presence {
+    /// Whether `other` shows the same, ignoring the player position, which
+    /// changes on every poll
+    pub fn shows_the_same_as(&self, other: &Presence) -> bool {
+        self.without_position() == other.without_position()
+    }
+
+    fn without_position(&self) -> Self {
+        let mut presence = self.clone();
+        if let Self::Playing { song, .. } = &mut presence {
+            song.player_position = 0.0;
+        }
+        presence
+    }
+}
+
+/// Broadcasts events to any number of subscribers. Publishing never waits
+/// for them; a subscriber that falls behind misses the oldest events.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { tx }
+    }
+
+    pub fn publish(&self, event: Event) {
+        // Fails only when nobody is subscribed
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+}
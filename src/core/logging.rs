@@ -11,7 +11,7 @@ use crate::core::error::{PipeBoomError, PipeBoomResult};
 
 pub fn setup_logging(verbosity: LevelFilter, max_log_size: u64) -> PipeBoomResult<()> {
     let home_dir = std::env::var("HOME").map_err(|e| {
-        PipeBoomError::Config(format!("Failed to get HOME environment variable: {}", e))
+        PipeBoomError::config("Failed to get HOME environment variable").with_source(e)
     })?;
 
     let log_path = format!("{}/Library/Logs/pipeboom.log", home_dir);
@@ -25,7 +25,10 @@ pub fn setup_logging(verbosity: LevelFilter, max_log_size: u64) -> PipeBoomResul
                     path,
                     max_log_size
                 );
-                fs::remove_file(path).map_err(|e| PipeBoomError::Io(e.to_string()))?;
+                fs::remove_file(path).map_err(|e| {
+                    PipeBoomError::io(format!("Failed to remove log file '{}'", path))
+                        .with_source(e)
+                })?;
             }
         }
     }
@@ -45,17 +48,20 @@ pub fn setup_logging(verbosity: LevelFilter, max_log_size: u64) -> PipeBoomResul
             ))
         });
 
-    let general_log = log_file(&log_path).map_err(|e| PipeBoomError::Io(e.to_string()))?;
+    let general_log = log_file(&log_path).map_err(|e| {
+        PipeBoomError::io(format!("Failed to open log file '{}'", log_path)).with_source(e)
+    })?;
 
-    let error_log = Dispatch::new()
-        .level(LevelFilter::Error)
-        .chain(log_file(&err_log_path).map_err(|e| PipeBoomError::Io(e.to_string()))?);
+    let error_file = log_file(&err_log_path).map_err(|e| {
+        PipeBoomError::io(format!("Failed to open log file '{}'", err_log_path)).with_source(e)
+    })?;
+    let error_log = Dispatch::new().level(LevelFilter::Error).chain(error_file);
 
     base_config
         .chain(general_log)
         .chain(error_log)
         .apply()
-        .map_err(|e| PipeBoomError::Internal(format!("Failed to initialize logger: {}", e)))?;
+        .map_err(|e| PipeBoomError::internal("Failed to initialize logger").with_source(e))?;
 
     Ok(())
 }
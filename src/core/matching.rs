@@ -0,0 +1,260 @@
+use crate::core::{
+    credits::{Credits, clean_title, fold},
+    models::Song,
+};
+
+/// Minimum score for a search result to be accepted as the current song
+pub const MATCH_THRESHOLD: f32 = 0.6;
+
+const TITLE_WEIGHT: f32 = 0.4;
+const ARTIST_WEIGHT: f32 = 0.3;
+const ALBUM_WEIGHT: f32 = 0.2;
+const DURATION_WEIGHT: f32 = 0.1;
+/// Share of the title and album scores given to the uncleaned text, so the
+/// exact edition beats a remaster or deluxe release of the same song
+const EDITION_WEIGHT: f32 = 0.1;
+
+/// Durations closer than this are treated as identical
+const DURATION_TOLERANCE_SECS: f32 = 2.0;
+/// Durations further apart than this score zero
+const DURATION_CUTOFF_SECS: f32 = 30.0;
+
+/// The fields of a search result that are compared against the playing song
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate<'a> {
+    pub title: &'a str,
+    pub artist: &'a str,
+    pub album: &'a str,
+    pub duration_ms: Option<u64>,
+}
+
+/// Scores how well `candidate` describes `song`, from 0.0 to 1.0
+pub fn score(song: &Song, candidate: &Candidate) -> f32 {
+    let song_credits = Credits::parse(&song.artist, &song.name);
+    let candidate_credits = Credits::parse(candidate.artist, candidate.title);
+    let artist_similarity = similarity(&song.artist, candidate.artist).max(similarity(
+        &song_credits.primary_artist,
+        &candidate_credits.primary_artist,
+    ));
+
+    let mut total = TITLE_WEIGHT * title_similarity(&song.name, candidate.title)
+        + ARTIST_WEIGHT * artist_similarity
+        + ALBUM_WEIGHT * title_similarity(&song.album, candidate.album);
+    let mut weights = TITLE_WEIGHT + ARTIST_WEIGHT + ALBUM_WEIGHT;
+
+    if let Some(duration_ms) = candidate.duration_ms.filter(|_| song.duration > 0.0) {
+        total += DURATION_WEIGHT * duration_similarity(song.duration, duration_ms as f32 / 1000.0);
+        weights += DURATION_WEIGHT;
+    }
+
+    total / weights
+}
+
+/// Returns the item that best matches `song`, if it scores at least
+/// [`MATCH_THRESHOLD`]. Every candidate's score is logged at debug level.
+pub fn best_match<'a, T>(
+    song: &Song,
+    items: &'a [T],
+    to_candidate: impl Fn(&'a T) -> Candidate<'a>,
+) -> Option<&'a T> {
+    let best = items
+        .iter()
+        .map(|item| {
+            let candidate = to_candidate(item);
+            let score = score(song, &candidate);
+            log::debug!(
+                "Candidate {:.2}: {} - {} ({})",
+                score,
+                candidate.artist,
+                candidate.title,
+                candidate.album
+            );
+            (item, score)
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+    match best {
+        Some((item, score)) if score >= MATCH_THRESHOLD => {
+            log::debug!("Best match scored {:.2}", score);
+            Some(item)
+        }
+        _ => {
+            log::debug!("No match above {:.2}", MATCH_THRESHOLD);
+            None
+        }
+    }
+}
+
+/// Dice coefficient over character bigrams of the normalized strings
+pub fn similarity(a: &str, b: &str) -> f32 {
+    let a = normalize(a);
+    let b = normalize(b);
+
+    if a == b {
+        return 1.0;
+    }
+
+    let a_bigrams = bigrams(&a);
+    let mut b_bigrams = bigrams(&b);
+
+    if a_bigrams.is_empty() || b_bigrams.is_empty() {
+        return 0.0;
+    }
+
+    let total = a_bigrams.len() + b_bigrams.len();
+    let mut shared = 0;
+
+    for bigram in a_bigrams {
+        if let Some(pos) = b_bigrams.iter().position(|b| *b == bigram) {
+            b_bigrams.swap_remove(pos);
+            shared += 1;
+        }
+    }
+
+    (2 * shared) as f32 / total as f32
+}
+
+/// Similarity of two titles with featuring clauses and edition suffixes
+/// mostly ignored
+fn title_similarity(a: &str, b: &str) -> f32 {
+    (1.0 - EDITION_WEIGHT) * similarity(&clean_title(a), &clean_title(b))
+        + EDITION_WEIGHT * similarity(a, b)
+}
+
+fn duration_similarity(a_secs: f32, b_secs: f32) -> f32 {
+    let diff = (a_secs - b_secs).abs();
+
+    if diff <= DURATION_TOLERANCE_SECS {
+        1.0
+    } else {
+        (1.0 - (diff - DURATION_TOLERANCE_SECS) / (DURATION_CUTOFF_SECS - DURATION_TOLERANCE_SECS))
+            .max(0.0)
+    }
+}
+
+fn normalize(text: &str) -> String {
+    fold(text)
+        .chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bigrams(text: &str) -> Vec<(char, char)> {
+    let chars = text.chars().collect::<Vec<_>>();
+    chars.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::core::models::ApiResults;
+
+    /// Recorded iTunes search response and the songs it was searched for
+    #[derive(Deserialize)]
+    struct SearchFixture {
+        cases: Vec<SearchCase>,
+        response: ApiResults,
+    }
+
+    #[derive(Deserialize)]
+    struct SearchCase {
+        playing: Song,
+        expected_track_id: Option<u64>,
+    }
+
+    fn check(name: &str, fixture: &str) {
+        let fixture = serde_json::from_str::<SearchFixture>(fixture).unwrap();
+
+        for case in fixture.cases {
+            let best = best_match(&case.playing, &fixture.response.results, |result| {
+                Candidate {
+                    title: result.track_name.as_deref().unwrap_or_default(),
+                    artist: &result.artist_name,
+                    album: &result.album_name,
+                    duration_ms: result.track_time_millis,
+                }
+            });
+
+            assert_eq!(
+                best.and_then(|result| result.track_id),
+                case.expected_track_id,
+                "{}: {} - {} ({})",
+                name,
+                case.playing.artist,
+                case.playing.name,
+                case.playing.album
+            );
+        }
+    }
+
+    #[test]
+    fn picks_matching_edition() {
+        check(
+            "remaster",
+            include_str!("../../tests/fixtures/matching/remaster.json"),
+        );
+    }
+
+    #[test]
+    fn prefers_studio_over_live() {
+        check(
+            "live",
+            include_str!("../../tests/fixtures/matching/live.json"),
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_artist() {
+        check(
+            "wrong_artist",
+            include_str!("../../tests/fixtures/matching/wrong_artist.json"),
+        );
+    }
+
+    #[test]
+    fn scores_wrong_artist_below_threshold() {
+        let fixture = serde_json::from_str::<SearchFixture>(include_str!(
+            "../../tests/fixtures/matching/wrong_artist.json"
+        ))
+        .unwrap();
+        let song = &fixture.cases[0].playing;
+
+        for result in &fixture.response.results {
+            let candidate = Candidate {
+                title: result.track_name.as_deref().unwrap_or_default(),
+                artist: &result.artist_name,
+                album: &result.album_name,
+                duration_ms: result.track_time_millis,
+            };
+            let score = score(song, &candidate);
+            assert!(
+                score < MATCH_THRESHOLD,
+                "{} scored {:.2}",
+                result.artist_name,
+                score
+            );
+        }
+    }
+
+    #[test]
+    fn compares_similarity() {
+        let cases = [
+            // (a, b, expected)
+            ("Beyoncé", "BEYONCE", 1.0),
+            ("Ｒｕｍｏｕｒｓ", "Rumours", 1.0),
+            ("Dreams", "Dreams!", 1.0),
+            ("abc", "xyz", 0.0),
+            ("a", "b", 0.0),
+        ];
+
+        for (a, b, expected) in cases {
+            assert_eq!(similarity(a, b), expected, "{:?} vs {:?}", a, b);
+        }
+    }
+}
@@ -1,5 +1,14 @@
+pub mod cache;
+pub mod clock;
+pub mod config;
 pub mod constants;
+pub mod credits;
 pub mod error;
+pub mod events;
 pub mod logging;
+pub mod matching;
 pub mod models;
+pub mod overrides;
+pub mod state;
+pub mod template;
 pub mod utils;
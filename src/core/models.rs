@@ -1,5 +1,9 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::core::credits::{clean_title, fold};
+
 #[derive(Debug, Copy, Clone, PartialEq, Serialize)]
 pub enum PlayerState {
     Playing,
@@ -39,10 +43,16 @@ pub struct ApiResults {
 pub struct ApiResult {
     #[serde(rename = "wrapperType")]
     pub wrapper_type: String,
+    #[serde(rename = "trackId")]
+    pub track_id: Option<u64>,
     #[serde(rename = "artistName")]
     pub artist_name: String,
     #[serde(rename = "collectionName")]
     pub album_name: String,
+    #[serde(rename = "trackName")]
+    pub track_name: Option<String>,
+    #[serde(rename = "trackTimeMillis")]
+    pub track_time_millis: Option<u64>,
     #[serde(rename = "artworkUrl100")]
     pub artwork_url: String,
     #[serde(rename = "collectionViewUrl")]
@@ -51,11 +61,162 @@ pub struct ApiResult {
     pub artist_url: Option<String>,
     #[serde(rename = "trackViewUrl")]
     pub song_url: Option<String>,
+    #[serde(rename = "trackNumber")]
+    pub track_number: Option<u32>,
+    #[serde(rename = "trackCount")]
+    pub track_count: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Deserialize, Debug)]
+pub struct MusicBrainzResults {
+    pub recordings: Vec<MusicBrainzRecording>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MusicBrainzRecording {
+    pub id: String,
+    pub title: String,
+    pub length: Option<u64>,
+    #[serde(rename = "artist-credit", default)]
+    pub artist_credit: Vec<MusicBrainzArtistCredit>,
+    #[serde(default)]
+    pub releases: Vec<MusicBrainzRelease>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MusicBrainzArtistCredit {
+    pub name: String,
+    #[serde(default)]
+    pub joinphrase: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct MusicBrainzRelease {
+    pub id: String,
+    pub title: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CoverArtResults {
+    pub images: Vec<CoverArtImage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CoverArtImage {
+    pub front: bool,
+    pub image: String,
+    #[serde(default)]
+    pub thumbnails: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeezerResults {
+    pub data: Vec<DeezerTrack>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeezerTrack {
+    pub title: String,
+    /// Duration in seconds
+    pub duration: Option<u64>,
+    pub link: String,
+    pub artist: DeezerArtist,
+    pub album: DeezerAlbum,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeezerArtist {
+    pub name: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeezerAlbum {
+    pub id: u64,
+    pub title: String,
+    pub cover_xl: Option<String>,
+    pub cover_big: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct OdesliResults {
+    pub page_url: String,
+    #[serde(default)]
+    pub links_by_platform: HashMap<String, OdesliLink>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OdesliLink {
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct LrclibResult {
+    pub track_name: String,
+    pub artist_name: String,
+    #[serde(default)]
+    pub album_name: String,
+    pub duration: Option<f64>,
+    pub synced_lyrics: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicSearchResults {
+    pub results: AppleMusicSearchGroups,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicSearchGroups {
+    pub songs: Option<AppleMusicSongs>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicSongs {
+    pub data: Vec<AppleMusicSong>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicSong {
+    #[serde(default)]
+    pub id: String,
+    pub attributes: AppleMusicSongAttributes,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AppleMusicSongAttributes {
+    pub name: String,
+    pub artist_name: String,
+    #[serde(default)]
+    pub album_name: String,
+    pub duration_in_millis: Option<u64>,
+    pub isrc: Option<String>,
+    pub url: String,
+    #[serde(default)]
+    pub genre_names: Vec<String>,
+    pub artwork: Option<AppleMusicArtwork>,
+    pub editorial_notes: Option<AppleMusicEditorialNotes>,
+    pub track_number: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicArtwork {
+    /// Template with `{w}` and `{h}` size placeholders
+    pub url: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AppleMusicEditorialNotes {
+    pub short: Option<String>,
+    pub standard: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Song {
     pub id: u32,
+    #[serde(rename = "persistentID", default)]
+    pub persistent_id: String,
     pub name: String,
     pub artist: String,
     pub album: String,
@@ -65,13 +226,88 @@ pub struct Song {
     pub duration: f32,
     #[serde(rename = "playerPosition")]
     pub player_position: f32,
+    #[serde(rename = "trackNumber", default)]
+    pub track_number: u32,
+    #[serde(rename = "trackCount", default)]
+    pub track_count: u32,
+    #[serde(default)]
+    pub genre: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SongDetails {
     pub artwork: String,
     pub album_url: String,
     pub song_url: String,
+    pub track_number: Option<u32>,
+    pub track_count: Option<u32>,
+    /// Name of the metadata provider these details came from
+    pub provider: Option<String>,
+    #[serde(default)]
+    pub links: ShareLinks,
+    #[serde(default)]
+    pub genres: Vec<String>,
+    #[serde(default)]
+    pub editorial_notes: Option<String>,
+    /// Why fallback artwork was chosen, if no provider had any
+    #[serde(default)]
+    pub fallback: Option<String>,
+    /// iTunes and Apple Music catalog ID of the song, to look it up directly
+    /// once these details expire
+    #[serde(default)]
+    pub catalog_id: Option<u64>,
+    /// Link to the song on the provider's own site, for providers other than
+    /// Apple Music
+    #[serde(default)]
+    pub source_url: Option<String>,
+    /// ISRC of the recording, when the provider reports one
+    #[serde(default)]
+    pub isrc: Option<String>,
+}
+
+/// Links to the song on other platforms, resolved with Odesli
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShareLinks {
+    pub universal_url: Option<String>,
+    pub spotify_url: Option<String>,
+    pub youtube_url: Option<String>,
+}
+
+impl Song {
+    /// Stable key for this track: its persistent ID, or the normalized
+    /// artist, title and album when Music doesn't provide one
+    pub fn identity(&self) -> String {
+        if !self.persistent_id.is_empty() {
+            return self.persistent_id.clone();
+        }
+
+        fold(&format!(
+            "{}|{}|{}",
+            self.artist,
+            clean_title(&self.name),
+            clean_title(&self.album)
+        ))
+        .to_lowercase()
+    }
+}
+
+/// A 200-second song, 1 as its ID and no persistent ID, for unit tests
+#[cfg(test)]
+pub fn test_song(artist: &str, name: &str, album: &str, album_artist: &str) -> Song {
+    Song {
+        id: 1,
+        persistent_id: String::new(),
+        name: name.to_string(),
+        artist: artist.to_string(),
+        album: album.to_string(),
+        album_artist: album_artist.to_string(),
+        year: 2000,
+        duration: 200.0,
+        player_position: 0.0,
+        track_number: 0,
+        track_count: 0,
+        genre: String::new(),
+    }
 }
 
 impl SongDetails {
@@ -80,6 +316,29 @@ impl SongDetails {
             artwork: artwork.replace('"', ""),
             album_url: album_url.replace('"', ""),
             song_url: song_url.replace('"', ""),
+            track_number: None,
+            track_count: None,
+            provider: None,
+            links: ShareLinks::default(),
+            genres: Vec::new(),
+            editorial_notes: None,
+            fallback: None,
+            catalog_id: None,
+            source_url: None,
+            isrc: None,
         }
     }
+
+    /// Whether these details have both artwork and a link to show
+    pub fn is_usable(&self) -> bool {
+        !self.artwork.is_empty()
+            && self.artwork != "no_art"
+            && (!self.song_url.is_empty() || !self.album_url.is_empty())
+    }
+
+    pub fn with_album_position(mut self, number: Option<u32>, count: Option<u32>) -> Self {
+        self.track_number = number;
+        self.track_count = count;
+        self
+    }
 }
@@ -0,0 +1,314 @@
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::core::{
+    error::{PipeBoomError, PipeBoomResult},
+    models::{Song, SongDetails},
+};
+
+#[derive(Debug, Default, Deserialize)]
+struct OverridesFile {
+    #[serde(default)]
+    rules: Vec<OverrideRule>,
+}
+
+/// Forces how a matching track is shown
+#[derive(Debug, Clone, Deserialize)]
+pub struct OverrideRule {
+    /// Shown by `pipeboom overrides test`
+    pub name: Option<String>,
+    #[serde(rename = "match")]
+    pub matcher: RuleMatch,
+    pub artwork: Option<String>,
+    pub song_url: Option<String>,
+    pub album_url: Option<String>,
+    /// Displayed instead of the track's title
+    pub title: Option<String>,
+    /// Displayed instead of the track's artist
+    pub artist: Option<String>,
+    /// Don't show the track at all
+    #[serde(default)]
+    pub hide: bool,
+}
+
+/// Conditions that must all hold for a rule to match
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RuleMatch {
+    pub persistent_id: Option<String>,
+    pub artist: Option<Pattern>,
+    pub title: Option<Pattern>,
+    pub album: Option<Pattern>,
+}
+
+/// A case-insensitive exact string, or `{ regex = "..." }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "PatternDef")]
+pub enum Pattern {
+    Exact(String),
+    Regex(Regex),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PatternDef {
+    Exact(String),
+    Regex { regex: String },
+}
+
+impl TryFrom<PatternDef> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(def: PatternDef) -> Result<Self, Self::Error> {
+        Ok(match def {
+            PatternDef::Exact(text) => Self::Exact(text),
+            PatternDef::Regex { regex } => Self::Regex(Regex::new(&regex)?),
+        })
+    }
+}
+
+impl Pattern {
+    fn matches(&self, text: &str) -> bool {
+        match self {
+            Self::Exact(expected) => expected.trim().eq_ignore_ascii_case(text.trim()),
+            Self::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl RuleMatch {
+    fn matches(&self, song: &Song) -> bool {
+        let matches = |pattern: &Option<Pattern>, text: &str| {
+            pattern.as_ref().is_none_or(|p| p.matches(text))
+        };
+        let has_condition = self.persistent_id.is_some()
+            || self.artist.is_some()
+            || self.title.is_some()
+            || self.album.is_some();
+
+        has_condition
+            && self
+                .persistent_id
+                .as_ref()
+                .is_none_or(|id| id.eq_ignore_ascii_case(&song.persistent_id))
+            && matches(&self.artist, &song.artist)
+            && matches(&self.title, &song.name)
+            && matches(&self.album, &song.album)
+    }
+}
+
+impl OverrideRule {
+    /// Applies this rule to `song` and `details`. Returns false if the track
+    /// should be hidden.
+    pub fn apply(&self, song: &mut Song, details: &mut SongDetails) -> bool {
+        self.apply_to_song(song);
+        self.apply_to_details(details);
+
+        !self.hide
+    }
+
+    /// Replaces the displayed title and artist
+    pub fn apply_to_song(&self, song: &mut Song) {
+        if let Some(title) = &self.title {
+            song.name = title.clone();
+        }
+        if let Some(artist) = &self.artist {
+            song.artist = artist.clone();
+        }
+    }
+
+    pub fn apply_to_details(&self, details: &mut SongDetails) {
+        if let Some(artwork) = &self.artwork {
+            details.artwork = artwork.clone();
+        }
+        if let Some(song_url) = &self.song_url {
+            details.song_url = song_url.clone();
+        }
+        if let Some(album_url) = &self.album_url {
+            details.album_url = album_url.clone();
+        }
+    }
+}
+
+/// Override rules from a TOML or JSON file, reloaded when the file changes
+#[derive(Debug)]
+pub struct Overrides {
+    path: PathBuf,
+    rules: Vec<OverrideRule>,
+    modified: Option<SystemTime>,
+}
+
+impl Overrides {
+    pub fn load(path: PathBuf) -> Self {
+        let mut overrides = Self {
+            path,
+            rules: Vec::new(),
+            modified: None,
+        };
+        overrides.reload_if_changed();
+
+        overrides
+    }
+
+    /// Loads the rules, failing if the file exists but can't be parsed
+    pub fn try_load(path: PathBuf) -> PipeBoomResult<Self> {
+        let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok();
+        let rules = match modified {
+            Some(_) => Self::read(&path)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            path,
+            rules,
+            modified,
+        })
+    }
+
+    /// Rereads the file if its modification time changed. Keeps the previous
+    /// rules if the new file can't be parsed.
+    pub fn reload_if_changed(&mut self) {
+        let modified = fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+            .ok();
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        if modified.is_none() {
+            if !self.rules.is_empty() {
+                log::info!("Overrides file {:?} removed", self.path);
+            }
+            self.rules.clear();
+            return;
+        }
+
+        match Self::read(&self.path) {
+            Ok(rules) => {
+                log::info!("Loaded {} override rules from {:?}", rules.len(), self.path);
+                self.rules = rules;
+            }
+            Err(e) => log::warn!("{:#}", e),
+        }
+    }
+
+    fn read(path: &Path) -> PipeBoomResult<Vec<OverrideRule>> {
+        let contents = fs::read_to_string(path)?;
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+
+        let file: Result<OverridesFile, Box<dyn Error + Send + Sync>> = if is_json {
+            serde_json::from_str(&contents).map_err(Into::into)
+        } else {
+            toml::from_str(&contents).map_err(Into::into)
+        };
+        let file = file.map_err(|e| {
+            PipeBoomError::config(format!("Failed to parse overrides file {:?}", path))
+                .with_source(e)
+        })?;
+
+        Ok(file.rules)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// First rule matching `song`, with its index in the file
+    pub fn find(&self, song: &Song) -> Option<(usize, &OverrideRule)> {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matcher.matches(song))
+    }
+
+    /// Applies the first matching rule. Returns false if the track should be
+    /// hidden.
+    pub fn apply(&self, song: &mut Song, details: &mut SongDetails) -> bool {
+        match self.find(song) {
+            Some((_, rule)) => rule.apply(song, details),
+            None => true,
+        }
+    }
+
+    /// `song` as displayed, to look its metadata up with, or None if the
+    /// track should be hidden
+    pub fn displayed(&self, song: &Song) -> Option<Song> {
+        let mut song = song.clone();
+        match self.find(&song) {
+            Some((_, rule)) if rule.hide => return None,
+            Some((_, rule)) => rule.apply_to_song(&mut song),
+            None => {}
+        }
+
+        Some(song)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{models::test_song, utils::temp_path};
+
+    fn overrides(name: &str, contents: &str) -> PipeBoomResult<Overrides> {
+        let path = temp_path(&format!("overrides-{}.toml", name));
+        fs::write(&path, contents).unwrap();
+        let overrides = Overrides::try_load(path.clone());
+        let _ = fs::remove_file(&path);
+
+        overrides
+    }
+
+    #[test]
+    fn displays_songs_before_lookups() {
+        let overrides = overrides(
+            "displayed",
+            r#"
+            [[rules]]
+            match = { artist = "White Noise" }
+            hide = true
+
+            [[rules]]
+            match = { title = { regex = "^Track \\d+$" } }
+            title = "Untitled"
+            artist = "Someone"
+            "#,
+        )
+        .unwrap();
+
+        // (artist, title, expected)
+        let cases = [
+            ("white noise", "Rain", None),
+            ("Band", "Track 12", Some(("Someone", "Untitled"))),
+            ("Band", "Track 12 (live)", Some(("Band", "Track 12 (live)"))),
+        ];
+
+        for (artist, title, expected) in cases {
+            let displayed = overrides.displayed(&test_song(artist, title, "Album", artist));
+            assert_eq!(
+                displayed
+                    .as_ref()
+                    .map(|song| (song.artist.as_str(), song.name.as_str())),
+                expected,
+                "{} - {}",
+                artist,
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn reports_parse_errors() {
+        let error = overrides("invalid", "[[rules]]\nmatch = 1\n").unwrap_err();
+
+        assert_eq!(error.code(), crate::core::error::ErrorCode::ConfigInvalid);
+        assert!(error.to_string().contains("Failed to parse overrides file"));
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::core::{
+    error::{PipeBoomError, PipeBoomResult},
+    utils::current_time_as_u64,
+};
+
+/// Lifecycle of the player controller
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerState {
+    #[default]
+    Stopped,
+    /// Started, but Discord or the music app isn't open yet
+    WaitingForApps,
+    Connecting,
+    /// Connected to Discord and polling the player
+    Active,
+    /// Waiting before reconnecting to Discord
+    Backoff,
+    /// Gave up after an unrecoverable error, until started again
+    Failed,
+}
+
+impl ControllerState {
+    /// Whether the controller may go from this state to `next`
+    pub fn can_transition_to(self, next: Self) -> bool {
+        use ControllerState::*;
+
+        match (self, next) {
+            (current, next) if current == next => false,
+            (_, Stopped) => true,
+            (Stopped | Failed | Active | Backoff, WaitingForApps) => true,
+            (WaitingForApps | Backoff, Connecting) => true,
+            (Connecting, Active) => true,
+            (Connecting | Active, Backoff) => true,
+            (WaitingForApps | Connecting | Active | Backoff, Failed) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for ControllerState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ControllerState::Stopped => "stopped",
+            ControllerState::WaitingForApps => "waiting for apps",
+            ControllerState::Connecting => "connecting",
+            ControllerState::Active => "active",
+            ControllerState::Backoff => "backing off",
+            ControllerState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// When a state was last entered and the last error that happened in it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateRecord {
+    pub entered_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ControllerStatus {
+    pub state: ControllerState,
+    /// When the current state was entered, in seconds since the epoch
+    pub since: u64,
+    /// Apps that aren't open yet while waiting for apps
+    pub waiting_for: Vec<String>,
+    pub states: HashMap<ControllerState, StateRecord>,
+}
+
+/// Tracks the controller's state and rejects transitions that aren't allowed.
+/// Clones share the same state, so it survives a restarted controller and can
+/// be read without waiting for it.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    status: Arc<Mutex<ControllerStatus>>,
+}
+
+impl Default for StateMachine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateMachine {
+    pub fn new() -> Self {
+        let machine = Self {
+            status: Arc::new(Mutex::new(ControllerStatus::default())),
+        };
+        Self::enter(&mut machine.lock(), ControllerState::Stopped);
+
+        machine
+    }
+
+    pub fn state(&self) -> ControllerState {
+        self.lock().state
+    }
+
+    pub fn is(&self, state: ControllerState) -> bool {
+        self.state() == state
+    }
+
+    pub fn transition(&self, next: ControllerState) -> PipeBoomResult<()> {
+        let mut status = self.lock();
+        let current = status.state;
+        if !current.can_transition_to(next) {
+            return Err(PipeBoomError::internal(format!(
+                "Invalid controller transition from {} to {}",
+                current, next
+            )));
+        }
+
+        log::debug!("Controller state: {} -> {}", current, next);
+        Self::enter(&mut status, next);
+
+        Ok(())
+    }
+
+    /// Records `error` against the current state
+    pub fn record_error(&self, error: &PipeBoomError) {
+        let mut status = self.lock();
+        let state = status.state;
+        status.states.entry(state).or_default().last_error = Some(format!("{:#}", error));
+    }
+
+    pub fn waiting_for(&self) -> Vec<String> {
+        self.lock().waiting_for.clone()
+    }
+
+    pub fn set_waiting_for(&self, apps: Vec<String>) {
+        self.lock().waiting_for = apps;
+    }
+
+    pub fn status(&self) -> ControllerStatus {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ControllerStatus> {
+        self.status.lock().unwrap()
+    }
+
+    fn enter(status: &mut ControllerStatus, state: ControllerState) {
+        let now = current_time_as_u64().unwrap_or_default();
+
+        status.state = state;
+        status.since = now;
+        if state != ControllerState::WaitingForApps {
+            status.waiting_for.clear();
+        }
+        status.states.entry(state).or_default().entered_at = Some(now);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Running,
+    /// Crashed and waiting to be restarted
+    Restarting,
+    /// Exited cleanly
+    Stopped,
+    /// Crashed too often and won't be restarted
+    GaveUp,
+}
+
+/// Health of a supervised task
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComponentHealth {
+    pub name: String,
+    pub status: HealthStatus,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::error::ErrorCode;
+
+    const STATES: [ControllerState; 6] = [
+        ControllerState::Stopped,
+        ControllerState::WaitingForApps,
+        ControllerState::Connecting,
+        ControllerState::Active,
+        ControllerState::Backoff,
+        ControllerState::Failed,
+    ];
+
+    /// Machine brought into `state` through allowed transitions
+    fn machine_in(state: ControllerState) -> StateMachine {
+        use ControllerState::*;
+
+        let path: &[ControllerState] = match state {
+            Stopped => &[],
+            WaitingForApps => &[WaitingForApps],
+            Connecting => &[WaitingForApps, Connecting],
+            Active => &[WaitingForApps, Connecting, Active],
+            Backoff => &[WaitingForApps, Connecting, Backoff],
+            Failed => &[WaitingForApps, Failed],
+        };
+        let machine = StateMachine::new();
+        for next in path {
+            machine.transition(*next).unwrap();
+        }
+
+        machine
+    }
+
+    #[test]
+    fn allows_only_listed_transitions() {
+        use ControllerState::*;
+
+        // (from, allowed next states)
+        let cases: [(ControllerState, &[ControllerState]); 6] = [
+            (Stopped, &[WaitingForApps]),
+            (WaitingForApps, &[Stopped, Connecting, Failed]),
+            (Connecting, &[Stopped, Active, Backoff, Failed]),
+            (Active, &[Stopped, WaitingForApps, Backoff, Failed]),
+            (Backoff, &[Stopped, WaitingForApps, Connecting, Failed]),
+            (Failed, &[Stopped, WaitingForApps]),
+        ];
+
+        for (from, allowed) in cases {
+            for next in STATES {
+                let expected = allowed.contains(&next);
+                assert_eq!(
+                    from.can_transition_to(next),
+                    expected,
+                    "{} -> {}",
+                    from,
+                    next
+                );
+
+                let machine = machine_in(from);
+                let result = machine.transition(next);
+                assert_eq!(result.is_ok(), expected, "{} -> {}", from, next);
+                if expected {
+                    assert_eq!(machine.state(), next);
+                    assert!(machine.status().states[&next].entered_at.is_some());
+                } else {
+                    assert_eq!(result.unwrap_err().code(), ErrorCode::Internal);
+                    assert_eq!(machine.state(), from);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn records_the_last_error_of_each_state() {
+        for state in STATES {
+            let machine = machine_in(state);
+            machine.record_error(&PipeBoomError::discord("first"));
+            machine.record_error(&PipeBoomError::discord(format!("last in {}", state)));
+
+            let status = machine.status();
+            assert_eq!(
+                status.states[&state].last_error,
+                Some(format!("last in {}", state)),
+                "{}",
+                state
+            );
+            for (other, record) in &status.states {
+                if *other != state {
+                    assert_eq!(record.last_error, None, "{} in {}", other, state);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_errors_after_leaving_a_state() {
+        let machine = machine_in(ControllerState::Connecting);
+        machine.record_error(&PipeBoomError::discord("refused"));
+        machine.transition(ControllerState::Backoff).unwrap();
+        machine.transition(ControllerState::Failed).unwrap();
+
+        let status = machine.status();
+        assert_eq!(status.state, ControllerState::Failed);
+        assert!(
+            status.states[&ControllerState::Connecting]
+                .last_error
+                .as_deref()
+                .is_some_and(|error| error.contains("refused"))
+        );
+        assert_eq!(status.states[&ControllerState::Failed].last_error, None);
+    }
+
+    #[test]
+    fn waits_for_apps_only_while_waiting() {
+        let machine = machine_in(ControllerState::WaitingForApps);
+        machine.set_waiting_for(vec!["Discord".to_string()]);
+        assert_eq!(machine.waiting_for(), ["Discord"]);
+
+        machine.transition(ControllerState::Connecting).unwrap();
+        assert!(machine.waiting_for().is_empty());
+    }
+
+    #[test]
+    fn clones_share_the_state() {
+        let machine = machine_in(ControllerState::WaitingForApps);
+        let restarted = machine.clone();
+
+        restarted.transition(ControllerState::Failed).unwrap();
+        restarted.record_error(&PipeBoomError::discord("gave up"));
+
+        assert_eq!(machine.state(), ControllerState::Failed);
+        assert!(
+            machine.status().states[&ControllerState::Failed]
+                .last_error
+                .is_some()
+        );
+    }
+}
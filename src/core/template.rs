@@ -0,0 +1,17 @@
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{(\w+)\}").unwrap());
+
+/// Replaces `{name}` placeholders in `template` with the values returned by
+/// `lookup`. Known placeholders without a value render as an empty string,
+/// unknown ones are left as they are.
+pub fn render(template: &str, lookup: impl Fn(&str) -> Option<Option<String>>) -> String {
+    PLACEHOLDER
+        .replace_all(template, |captures: &Captures| match lookup(&captures[1]) {
+            Some(value) => value.unwrap_or_default(),
+            None => captures[0].to_string(),
+        })
+        .into_owned()
+}
@@ -1,21 +1,11 @@
-pub const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
-
 use std::{
+    env,
     process::Command,
     time::{SystemTime, UNIX_EPOCH},
 };
 
-use percent_encoding::{AsciiSet, CONTROLS};
-
 use crate::core::error::{PipeBoomError, PipeBoomResult};
 
-pub fn truncate(text: &str, max_length: usize) -> &str {
-    match text.char_indices().nth(max_length) {
-        Some((idx, _)) => &text[..idx],
-        None => text,
-    }
-}
-
 pub fn current_time_as_u64() -> PipeBoomResult<u64> {
     let start = SystemTime::now();
     let since_the_epoch = start.duration_since(UNIX_EPOCH)?;
@@ -23,6 +13,21 @@ pub fn current_time_as_u64() -> PipeBoomResult<u64> {
     Ok(since_the_epoch.as_secs())
 }
 
+/// Path of a scratch file unique to this test process, removed beforehand
+#[cfg(test)]
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    let path = env::temp_dir().join(format!("pipeboom-test-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+/// 64-bit FNV-1a, for hashes that must stay stable across builds
+pub fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 pub fn macos_ver() -> PipeBoomResult<f32> {
     let output_result = Command::new("sh")
         .arg("-c")
@@ -33,7 +38,7 @@ pub fn macos_ver() -> PipeBoomResult<f32> {
         Ok(o) => {
             if !o.status.success() {
                 let stderr = String::from_utf8_lossy(&o.stderr);
-                return Err(PipeBoomError::Io(format!(
+                return Err(PipeBoomError::io(format!(
                     "sw_vers command failed: {}",
                     stderr
                 )));
@@ -47,7 +52,7 @@ pub fn macos_ver() -> PipeBoomResult<f32> {
     let ver_parts = ver_str.trim().split('.').collect::<Vec<&str>>();
 
     if ver_parts.len() < 2 {
-        return Err(PipeBoomError::Parse(format!(
+        return Err(PipeBoomError::parse(format!(
             "Unexpected macOS version format: {}",
             ver_str.trim()
         )));
@@ -59,3 +64,41 @@ pub fn macos_ver() -> PipeBoomResult<f32> {
 
     Ok(ver_float_str.parse::<f32>()?)
 }
+
+/// Returns the system's language and, if set, region, e.g. `("ja", Some("jp"))`
+pub fn system_locale() -> Option<(String, Option<String>)> {
+    let apple_locale = Command::new("defaults")
+        .args(["read", "-g", "AppleLocale"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string());
+
+    let locale = apple_locale
+        .into_iter()
+        .chain(
+            ["LC_ALL", "LC_MESSAGES", "LANG"]
+                .map(|var| env::var(var).ok())
+                .into_iter()
+                .flatten(),
+        )
+        .find(|l| !l.is_empty() && l != "C" && l != "POSIX")?;
+
+    parse_locale(&locale)
+}
+
+fn parse_locale(locale: &str) -> Option<(String, Option<String>)> {
+    let locale = locale.split(['.', '@']).next()?;
+    let mut parts = locale.split(['_', '-']);
+
+    let language = parts.next()?.to_lowercase();
+    if language.is_empty() {
+        return None;
+    }
+
+    let country = parts
+        .rfind(|p| p.len() == 2 && p.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|p| p.to_lowercase());
+
+    Some((language, country))
+}
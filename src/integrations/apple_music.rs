@@ -4,8 +4,9 @@ use serde::de::DeserializeOwned;
 use serde_json::Value;
 
 use crate::core::{
-    error::{PipeBoomError, PipeBoomResult},
+    error::{ErrorCode, PipeBoomError, PipeBoomResult},
     models::{PlayerState, Song},
+    utils::macos_ver,
 };
 
 fn run_osascript<T: DeserializeOwned>(script: String) -> PipeBoomResult<T> {
@@ -21,20 +22,24 @@ fn run_osascript<T: DeserializeOwned>(script: String) -> PipeBoomResult<T> {
         Ok(o) => {
             if !o.status.success() {
                 let stderr = String::from_utf8_lossy(&o.stderr);
-                return Err(PipeBoomError::Osascript(format!(
-                    "Osascript execution failed for script '{}': {} (exit code: {})",
-                    script,
-                    stderr.trim(),
-                    o.status.code().unwrap_or(-1)
-                )));
+                return Err(PipeBoomError::new(
+                    script_error_code(&stderr),
+                    format!(
+                        "Osascript execution failed for script '{}': {} (exit code: {})",
+                        script,
+                        stderr.trim(),
+                        o.status.code().unwrap_or(-1)
+                    ),
+                ));
             }
             o.stdout
         }
         Err(e) => {
-            return Err(PipeBoomError::Osascript(format!(
-                "Failed to execute osascript: {} (script: {})",
-                e, script
-            )));
+            return Err(PipeBoomError::apple_music(format!(
+                "Failed to execute osascript (script: {})",
+                script
+            ))
+            .with_source(e));
         }
     };
 
@@ -46,10 +51,52 @@ fn run_osascript<T: DeserializeOwned>(script: String) -> PipeBoomResult<T> {
             e,
             res
         );
-        PipeBoomError::Parse(format!("Failed to parse Apple Music script output: {}", e))
+        PipeBoomError::parse("Failed to parse Apple Music script output").with_source(e)
     })
 }
 
+/// Code for an Osascript failure, from the Apple event error number it printed
+fn script_error_code(stderr: &str) -> ErrorCode {
+    // -1743: not authorized to send Apple events, -600: app isn't running
+    if stderr.contains("-1743") {
+        ErrorCode::MusicPermissionDenied
+    } else if stderr.contains("-600") {
+        ErrorCode::MusicNotRunning
+    } else {
+        ErrorCode::MusicScriptFailed
+    }
+}
+
+/// Name of the music app: "Music" since macOS 10.15, "iTunes" before
+pub fn music_app_name() -> &'static str {
+    match macos_ver() {
+        Ok(ver) if ver >= 10.15 => "Music",
+        Ok(_) => "iTunes",
+        Err(e) => {
+            log::warn!(
+                "Failed to determine macOS version: {:#}. Defaulting app name to 'Music'.",
+                e
+            );
+            "Music"
+        }
+    }
+}
+
+/// Tells whether an app is running, so the controller can be tested without
+/// Discord or the music app
+pub trait ProcessProbe: Send + Sync {
+    fn is_open(&self, app_name: &str) -> PipeBoomResult<bool>;
+}
+
+/// Asks System Events through Osascript
+pub struct SystemEventsProbe;
+
+impl ProcessProbe for SystemEventsProbe {
+    fn is_open(&self, app_name: &str) -> PipeBoomResult<bool> {
+        get_is_open(app_name)
+    }
+}
+
 pub fn get_is_open(app_name: &str) -> PipeBoomResult<bool> {
     let script = format!(
         "Application('System Events').processes['{}'].exists()",
@@ -84,15 +131,55 @@ pub fn get_current_song(app_name: &str) -> PipeBoomResult<Option<Song>> {
             }
             serde_json::from_value::<Song>(val)
                 .map(Some)
-                .map_err(|e| PipeBoomError::Parse(format!("Failed to parse song data: {}", e)))
+                .map_err(|e| PipeBoomError::parse("Failed to parse song data").with_source(e))
         }
-        Err(PipeBoomError::Osascript(msg)) => {
-            log::warn!("Assuming no song due to AppleScript error: {}", msg);
+        Err(e) if e.code() == ErrorCode::MusicScriptFailed => {
+            log::warn!("Assuming no song due to AppleScript error: {:#}", e);
             Ok(None)
         }
         Err(e) => {
-            log::error!("Failed to get current song: {}", e);
+            log::error!("Failed to get current song: {:#}", e);
             Err(e)
         }
     }
 }
+
+pub fn get_playlist_position(app_name: &str) -> PipeBoomResult<Option<(u32, u32)>> {
+    let script = format!(
+        "(() => {{
+          const ids = Application('{0}').currentPlaylist().tracks.persistentID();
+          const index = ids.indexOf(Application('{0}').currentTrack().persistentID());
+          return index < 0 ? null : [index + 1, ids.length];
+        }})()",
+        app_name
+    );
+
+    match run_osascript::<Option<(u32, u32)>>(script) {
+        Ok(position) => Ok(position),
+        Err(e) if e.code() == ErrorCode::MusicScriptFailed => {
+            log::debug!("No playlist position available: {:#}", e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// POSIX path of the current track's file, if it is a local file
+pub fn get_track_location(app_name: &str) -> PipeBoomResult<Option<String>> {
+    let script = format!(
+        "(() => {{
+          const location = Application('{0}').currentTrack().location();
+          return location ? location.toString() : null;
+        }})()",
+        app_name
+    );
+
+    match run_osascript::<Option<String>>(script) {
+        Ok(location) => Ok(location),
+        Err(e) if e.code() == ErrorCode::MusicScriptFailed => {
+            log::debug!("No track location available: {:#}", e);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
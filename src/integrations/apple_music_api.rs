@@ -0,0 +1,284 @@
+use std::{fs, sync::Mutex};
+
+use async_trait::async_trait;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    core::{
+        config::{AppleMusicConfig, Storefront},
+        credits::{Credits, clean_title, fold},
+        error::{PipeBoomError, PipeBoomResult},
+        matching::{Candidate, best_match},
+        models::{AppleMusicSearchResults, AppleMusicSong, AppleMusicSongs, Song, SongDetails},
+        utils::current_time_as_u64,
+    },
+    integrations::{http::fetch_json, metadata::MetadataProvider},
+};
+
+const SONG_CANDIDATES: u8 = 10;
+/// Lifetime of locally signed developer tokens
+const TOKEN_LIFETIME: u64 = 12 * 60 * 60;
+/// Signed tokens are renewed when they expire within this many seconds
+const TOKEN_RENEWAL: u64 = 5 * 60;
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+/// Developer token sent with every catalog request
+enum DeveloperToken {
+    Static(String),
+    /// Signed locally with a MusicKit key, cached with its expiry
+    Signed {
+        key: EncodingKey,
+        key_id: String,
+        team_id: String,
+        cached: Mutex<Option<(String, u64)>>,
+    },
+}
+
+impl DeveloperToken {
+    fn from_config(config: &AppleMusicConfig) -> PipeBoomResult<Self> {
+        if let Some(token) = &config.developer_token {
+            return Ok(Self::Static(token.clone()));
+        }
+
+        let (Some(key_path), Some(key_id), Some(team_id)) =
+            (&config.key_path, &config.key_id, &config.team_id)
+        else {
+            return Err(PipeBoomError::config(
+                "Apple Music needs either developer_token or key_path, key_id and team_id"
+                    .to_string(),
+            ));
+        };
+
+        let key = EncodingKey::from_ec_pem(&fs::read(key_path)?).map_err(|e| {
+            PipeBoomError::config(format!("Invalid Apple Music key {:?}", key_path)).with_source(e)
+        })?;
+
+        Ok(Self::Signed {
+            key,
+            key_id: key_id.clone(),
+            team_id: team_id.clone(),
+            cached: Mutex::new(None),
+        })
+    }
+
+    fn get(&self) -> PipeBoomResult<String> {
+        let (key, key_id, team_id, cached) = match self {
+            Self::Static(token) => return Ok(token.clone()),
+            Self::Signed {
+                key,
+                key_id,
+                team_id,
+                cached,
+            } => (key, key_id, team_id, cached),
+        };
+
+        let now = current_time_as_u64()?;
+        let mut cached = cached.lock().unwrap();
+        if let Some((token, expires_at)) = cached.as_ref() {
+            if *expires_at > now + TOKEN_RENEWAL {
+                return Ok(token.clone());
+            }
+        }
+
+        let mut header = Header::new(Algorithm::ES256);
+        header.kid = Some(key_id.clone());
+        let claims = Claims {
+            iss: team_id,
+            iat: now,
+            exp: now + TOKEN_LIFETIME,
+        };
+
+        log::debug!("Signing a new Apple Music developer token");
+        let token = jsonwebtoken::encode(&header, &claims, key).map_err(|e| {
+            PipeBoomError::config("Failed to sign Apple Music token").with_source(e)
+        })?;
+        *cached = Some((token.clone(), claims.exp));
+
+        Ok(token)
+    }
+}
+
+/// Resolves songs with the Apple Music catalog API, by ISRC when previously
+/// resolved details have one
+pub struct AppleMusicApi {
+    base_url: String,
+    storefront: Storefront,
+    token: DeveloperToken,
+}
+
+impl AppleMusicApi {
+    pub fn new(
+        base_url: String,
+        storefront: Storefront,
+        config: &AppleMusicConfig,
+    ) -> PipeBoomResult<Self> {
+        Ok(Self {
+            base_url,
+            storefront,
+            token: DeveloperToken::from_config(config)?,
+        })
+    }
+
+    /// Resolves the song in one storefront, by ISRC first when it's known
+    async fn find(
+        &self,
+        song: &Song,
+        isrc: Option<&str>,
+        country: &str,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        if let Some(isrc) = isrc {
+            if let Some(details) = self.lookup_isrc(song, isrc, country).await? {
+                return Ok(Some(details));
+            }
+        }
+
+        self.search(song, country).await
+    }
+
+    async fn lookup_isrc(
+        &self,
+        song: &Song,
+        isrc: &str,
+        country: &str,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        let songs = self
+            .request::<AppleMusicSongs>(
+                country,
+                &format!(
+                    "songs?filter[isrc]={}",
+                    utf8_percent_encode(isrc, NON_ALPHANUMERIC)
+                ),
+            )
+            .await?;
+
+        Ok(Self::best(song, &songs.data).map(song_details))
+    }
+
+    async fn search(&self, song: &Song, country: &str) -> PipeBoomResult<Option<SongDetails>> {
+        let credits = Credits::parse(&song.artist, &song.name);
+        let term = fold(&format!(
+            "{} {}",
+            credits.primary_artist,
+            clean_title(&song.name)
+        ));
+        let results = self
+            .request::<AppleMusicSearchResults>(
+                country,
+                &format!(
+                    "search?types=songs&limit={}&term={}",
+                    SONG_CANDIDATES,
+                    utf8_percent_encode(&term, NON_ALPHANUMERIC)
+                ),
+            )
+            .await?;
+
+        let songs = results.results.songs.map(|s| s.data).unwrap_or_default();
+
+        Ok(Self::best(song, &songs).map(song_details))
+    }
+
+    fn best<'a>(song: &Song, songs: &'a [AppleMusicSong]) -> Option<&'a AppleMusicSong> {
+        best_match(song, songs, |found| Candidate {
+            title: &found.attributes.name,
+            artist: &found.attributes.artist_name,
+            album: &found.attributes.album_name,
+            duration_ms: found.attributes.duration_in_millis,
+        })
+    }
+
+    async fn request<T: DeserializeOwned>(&self, country: &str, path: &str) -> PipeBoomResult<T> {
+        let mut url = format!(
+            "{}/v1/catalog/{}/{}",
+            self.base_url.trim_end_matches('/'),
+            country,
+            path
+        );
+        if let Some(language) = &self.storefront.language {
+            url.push_str(&format!("&l={}", language.replace('_', "-")));
+        }
+
+        log::debug!("Requesting Apple Music: {}", url);
+
+        let request =
+            surf::get(url).header("Authorization", format!("Bearer {}", self.token.get()?));
+        fetch_json::<T>(request).await
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for AppleMusicApi {
+    fn name(&self) -> &'static str {
+        "apple_music"
+    }
+
+    fn links_to_apple_music(&self) -> bool {
+        true
+    }
+
+    async fn get_details(
+        &self,
+        song: &Song,
+        known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        let isrc = known.and_then(|known| known.isrc.as_deref());
+        let country = &self.storefront.country;
+        if let Some(details) = self.find(song, isrc, country).await? {
+            return Ok(Some(details));
+        }
+
+        match &self.storefront.fallback_country {
+            Some(fallback) if fallback != country => {
+                log::debug!(
+                    "Not found in the '{}' storefront, retrying in '{}'",
+                    country,
+                    fallback
+                );
+                self.find(song, isrc, fallback).await
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+fn song_details(found: &AppleMusicSong) -> SongDetails {
+    let attributes = &found.attributes;
+    // Song URLs point at the album with the song selected, e.g. `.../album/x/1?i=2`
+    let album_url = attributes
+        .url
+        .split_once('?')
+        .map_or(attributes.url.as_str(), |(album, _)| album);
+
+    let mut details = SongDetails::new(
+        attributes
+            .artwork
+            .as_ref()
+            .map(|artwork| artwork.url.clone())
+            .unwrap_or_default(),
+        album_url.to_string(),
+        attributes.url.clone(),
+    )
+    .with_album_position(attributes.track_number, None);
+
+    details.genres = attributes
+        .genre_names
+        .iter()
+        .filter(|genre| *genre != "Music")
+        .cloned()
+        .collect();
+    details.editorial_notes = attributes
+        .editorial_notes
+        .as_ref()
+        .and_then(|notes| notes.short.clone().or_else(|| notes.standard.clone()));
+    details.catalog_id = found.id.parse().ok();
+    details.isrc = attributes.isrc.clone();
+
+    details
+}
@@ -0,0 +1,210 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use regex::Regex;
+
+use crate::{
+    core::{config::FallbackConfig, credits::fold, models::Song, utils::fnv1a},
+    integrations::http::get_http_client,
+};
+
+/// Sizes tried, largest first, when the requested one isn't available
+const FALLBACK_SIZES: [u32; 4] = [1024, 600, 300, 100];
+
+/// Upper bound on remembered URL rewrites before the memo is reset
+const MEMO_CAPACITY: usize = 256;
+
+/// The size segment of an mzstatic artwork URL, e.g. `100x100bb.jpg` or the
+/// catalog API's `{w}x{h}bb.jpg` template
+static SIZE_SEGMENT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"/(?:\d+|\{w\})x(?:\d+|\{h\})([a-z]*)\.(jpg|jpeg|png|webp)$").unwrap()
+});
+
+static RESOLVED: LazyLock<Mutex<HashMap<(String, u32), String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Rewrites an mzstatic artwork URL or template to `size`x`size` pixels.
+/// Returns `None` for URLs that aren't resizable.
+pub fn resize(url: &str, size: u32) -> Option<String> {
+    if !url.contains("mzstatic.com") && !url.contains("{w}") {
+        return None;
+    }
+
+    let captures = SIZE_SEGMENT.captures(url)?;
+    let suffix = captures.get(1).map_or("", |m| m.as_str());
+    let suffix = if suffix.is_empty() { "bb" } else { suffix };
+
+    Some(
+        SIZE_SEGMENT
+            .replace(url, format!("/{size}x{size}{suffix}.{}", &captures[2]))
+            .into_owned(),
+    )
+}
+
+/// Returns the largest available version of `url` up to `size` pixels,
+/// falling back to smaller sizes and finally to `url` itself. Probing gives
+/// up after `timeout`, like the lookup that found `url`.
+pub async fn resolve(url: &str, size: u32, timeout: Duration) -> String {
+    let key = (url.to_string(), size);
+    if let Some(resolved) = RESOLVED.lock().unwrap().get(&key) {
+        return resolved.clone();
+    }
+
+    let sizes = std::iter::once(size).chain(FALLBACK_SIZES.into_iter().filter(|s| *s < size));
+    let candidates = sizes.filter_map(|s| resize(url, s)).collect::<Vec<_>>();
+
+    let smallest = || {
+        candidates
+            .last()
+            .cloned()
+            .unwrap_or_else(|| url.to_string())
+    };
+
+    let available = match tokio::time::timeout(timeout, probe(&candidates)).await {
+        Ok(Some(available)) => available,
+        Ok(None) => {
+            // Don't remember anything when the network is down
+            return smallest();
+        }
+        Err(_) => {
+            log::debug!("Checking artwork {} timed out after {:?}", url, timeout);
+            return smallest();
+        }
+    };
+
+    let resolved = available.unwrap_or_else(|| {
+        if url.contains("{w}") {
+            smallest()
+        } else {
+            url.to_string()
+        }
+    });
+
+    let mut memo = RESOLVED.lock().unwrap();
+    if memo.len() >= MEMO_CAPACITY {
+        memo.clear();
+    }
+    memo.insert(key, resolved.clone());
+
+    resolved
+}
+
+/// Returns the first of `candidates` that exists, or `None` if the network
+/// failed before one was found
+async fn probe(candidates: &[String]) -> Option<Option<String>> {
+    for candidate in candidates {
+        match get_http_client().head(candidate).await {
+            Ok(response) if response.status().is_success() => {
+                return Some(Some(candidate.clone()));
+            }
+            Ok(response) => {
+                log::debug!("Artwork {} unavailable ({})", candidate, response.status());
+            }
+            Err(e) => {
+                log::debug!("Failed to check artwork {}: {}", candidate, e);
+                return None;
+            }
+        }
+    }
+
+    Some(None)
+}
+
+/// Chooses artwork for tracks no provider has artwork for
+pub struct FallbackPolicy {
+    genres: HashMap<String, String>,
+    palette: Vec<String>,
+    default_image: Option<String>,
+}
+
+impl FallbackPolicy {
+    pub fn new(config: &FallbackConfig) -> Self {
+        Self {
+            genres: config
+                .genres
+                .iter()
+                .map(|(genre, image)| (genre.trim().to_lowercase(), image.clone()))
+                .collect(),
+            palette: config.palette.clone(),
+            default_image: config.default_image.clone(),
+        }
+    }
+
+    /// Returns the image for `song` and a short description of the choice
+    pub fn choose(&self, song: &Song) -> (String, String) {
+        let genre = song.genre.trim().to_lowercase();
+        if let Some(image) = self.genres.get(&genre) {
+            return (image.clone(), format!("genre:{}", genre));
+        }
+
+        if !self.palette.is_empty() {
+            let album = fold(&format!("{}|{}", song.album_artist, song.album)).to_lowercase();
+            let index = (fnv1a(&album) % self.palette.len() as u64) as usize;
+            return (self.palette[index].clone(), format!("palette:{}", index));
+        }
+
+        match &self.default_image {
+            Some(image) => (image.clone(), "default_image".to_string()),
+            None => ("no_art".to_string(), "no_art".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resizes_apple_artwork() {
+        let cases = [
+            // (url, size, expected)
+            (
+                "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/{w}x{h}bb.jpg",
+                600,
+                Some(
+                    "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/600x600bb.jpg",
+                ),
+            ),
+            (
+                "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/100x100bb.jpg",
+                1024,
+                Some(
+                    "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/1024x1024bb.jpg",
+                ),
+            ),
+            (
+                "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/100x100.png",
+                300,
+                Some(
+                    "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/300x300bb.png",
+                ),
+            ),
+            (
+                "https://is1-ssl.mzstatic.com/image/thumb/Music/v4/ab/cd/source/cover.jpg",
+                600,
+                None,
+            ),
+            (
+                "https://coverartarchive.org/release/1234/front-500.jpg",
+                600,
+                None,
+            ),
+            ("https://example.com/art/100x100bb.jpg", 600, None),
+            ("no_art", 600, None),
+        ];
+
+        for (url, size, expected) in cases {
+            assert_eq!(resize(url, size).as_deref(), expected, "{:?}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_non_apple_artwork_without_probing() {
+        let url = "https://coverartarchive.org/release/1234/front-500.jpg";
+
+        assert_eq!(resolve(url, 600, Duration::ZERO).await, url);
+    }
+}
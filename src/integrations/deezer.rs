@@ -0,0 +1,72 @@
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::{
+    core::{
+        credits::{Credits, clean_title},
+        error::PipeBoomResult,
+        matching::{Candidate, best_match},
+        models::{DeezerResults, Song, SongDetails},
+    },
+    integrations::{http::fetch_json, metadata::MetadataProvider},
+};
+
+/// Resolves songs with Deezer's public search API
+pub struct Deezer {
+    base_url: String,
+}
+
+impl Deezer {
+    pub fn new(base_url: String) -> Self {
+        Self { base_url }
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for Deezer {
+    fn name(&self) -> &'static str {
+        "deezer"
+    }
+
+    async fn get_details(
+        &self,
+        song: &Song,
+        _known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        let credits = Credits::parse(&song.artist, &song.name);
+        let query = format!(
+            "artist:\"{}\" track:\"{}\"",
+            credits.primary_artist.replace('"', ""),
+            clean_title(&song.name).replace('"', "")
+        );
+        let url = format!(
+            "{}/search?q={}",
+            self.base_url.trim_end_matches('/'),
+            utf8_percent_encode(&query, NON_ALPHANUMERIC)
+        );
+
+        log::debug!("Searching Deezer: {}", url);
+
+        let results = fetch_json::<DeezerResults>(surf::get(url)).await?;
+
+        let best = best_match(song, &results.data, |track| Candidate {
+            title: &track.title,
+            artist: &track.artist.name,
+            album: &track.album.title,
+            duration_ms: track.duration.map(|secs| secs * 1000),
+        });
+
+        Ok(best.map(|track| {
+            SongDetails::new(
+                track
+                    .album
+                    .cover_xl
+                    .clone()
+                    .or_else(|| track.album.cover_big.clone())
+                    .unwrap_or_default(),
+                format!("https://www.deezer.com/album/{}", track.album.id),
+                track.link.clone(),
+            )
+        }))
+    }
+}
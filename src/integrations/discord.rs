@@ -1,34 +1,59 @@
-use discord_rich_presence::{
-    DiscordIpc, DiscordIpcClient,
-    activity::{Activity, Assets, Button, Timestamps},
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
+
+use crate::{
+    core::{
+        config::PresenceConfig,
+        constants::DISCORD_APP_ID,
+        error::{PipeBoomError, PipeBoomResult},
+        models::{Song, SongDetails},
+        template::render,
+        utils::current_time_as_u64,
+    },
+    integrations::presence::{ActivityButton, ActivityPayload},
 };
 
-use crate::core::{
-    constants::DISCORD_APP_ID,
-    error::{PipeBoomError, PipeBoomResult},
-    models::{Song, SongDetails},
-    utils::{current_time_as_u64, truncate},
-};
+/// Opens Discord connections, so the controller can be tested without Discord
+pub trait DiscordConnector: Send + Sync {
+    fn connect(&self, templates: PresenceConfig) -> PipeBoomResult<DiscordClient>;
+}
+
+/// Connects over Discord's local IPC socket
+pub struct IpcConnector;
+
+impl DiscordConnector for IpcConnector {
+    fn connect(&self, templates: PresenceConfig) -> PipeBoomResult<DiscordClient> {
+        let mut client = DiscordClient::new(templates);
+        client.connect()?;
+
+        Ok(client)
+    }
+}
 
 pub struct DiscordClient {
     client: DiscordIpcClient,
+    templates: PresenceConfig,
     pub is_connected: bool,
 }
 
 impl DiscordClient {
-    pub fn new() -> Self {
+    pub fn new(templates: PresenceConfig) -> Self {
         let client = DiscordIpcClient::new(DISCORD_APP_ID);
 
         Self {
             client,
+            templates,
             is_connected: false,
         }
     }
 
+    pub fn set_templates(&mut self, templates: PresenceConfig) {
+        self.templates = templates;
+    }
+
     pub fn connect(&mut self) -> PipeBoomResult<()> {
         if self.is_connected {
-            return Err(PipeBoomError::Discord(
-                "Tried connecting to Discord IPC with an existing connection".into(),
+            return Err(PipeBoomError::discord(
+                "Tried connecting to Discord IPC with an existing connection",
             ));
         }
 
@@ -51,7 +76,13 @@ impl DiscordClient {
         Ok(())
     }
 
-    pub fn update_activity(&mut self, song: &Song, details: &SongDetails) -> PipeBoomResult<()> {
+    pub fn update_activity(
+        &mut self,
+        song: &Song,
+        details: &SongDetails,
+        position: Option<(u32, u32)>,
+        lyric: Option<&str>,
+    ) -> PipeBoomResult<()> {
         if !self.is_connected {
             return Ok(());
         }
@@ -59,50 +90,80 @@ impl DiscordClient {
         let current_time = match current_time_as_u64() {
             Ok(time) => time,
             Err(e) => {
-                log::error!("Failed to get current time for Discord activity: {}", e);
+                log::error!("Failed to get current time for Discord activity: {:#}", e);
                 0
             }
         };
 
-        let timestamps = Timestamps::new().start(
-            (current_time - song.player_position as u64)
-                .try_into()
-                .unwrap_or(0),
-        );
-
-        let assets = Assets::new()
-            .small_image("apple_music_logo")
-            .large_image(&details.artwork)
-            .large_text(truncate(&song.album, 128));
-
-        let buttons = vec![
-            Button::new(
-                "Listen on Apple Music",
-                if !details.song_url.is_empty() {
-                    &details.song_url
-                } else if !details.album_url.is_empty() {
-                    &details.album_url
-                } else {
-                    "https://music.apple.com/"
-                },
-            ),
-            Button::new(
-                "Share your AM status too!",
-                "https://shadhaan.me/api/projects/pipeboom",
-            ),
-        ];
-
-        let activity = Activity::new()
-            .state(truncate(&song.artist, 128))
-            .activity_type(discord_rich_presence::activity::ActivityType::Listening)
-            .details(truncate(&song.name, 128))
-            .timestamps(timestamps)
-            .assets(assets)
-            .buttons(buttons);
-
-        if let Err(e) = self.client.set_activity(activity) {
-            log::warn!("Failed to update Discord activity: {}", e);
-            return Err(PipeBoomError::Discord(e.to_string()));
+        let lookup = |name: &str| placeholder(name, song, details, lyric);
+        let state = render(&self.templates.state, lookup);
+
+        let payload = ActivityPayload {
+            details: Some(render(&self.templates.details, lookup)),
+            state: Some(if state.trim().is_empty() {
+                song.artist.clone()
+            } else {
+                state
+            }),
+            large_image: Some(details.artwork.clone()),
+            large_text: Some(song.album.clone()),
+            small_image: Some("apple_music_logo".to_string()),
+            small_text: None,
+            start_timestamp: Some(current_time as i64 - song.player_position as i64),
+            party_size: position
+                .filter(|(current, max)| *current > 0 && *max > 0)
+                .map(|(current, max)| [current, max]),
+            buttons: self.buttons(lookup),
+        };
+
+        self.set_activity(payload)
+    }
+
+    /// Shows `song` as the last track listened to, with the time elapsed since
+    /// `stopped_at`
+    pub fn update_last_listened(
+        &mut self,
+        song: &Song,
+        details: &SongDetails,
+        stopped_at: u64,
+    ) -> PipeBoomResult<()> {
+        if !self.is_connected {
+            return Ok(());
+        }
+
+        let lookup = |name: &str| placeholder(name, song, details, None);
+
+        let payload = ActivityPayload {
+            details: Some(render(&self.templates.last_listened_details, lookup)),
+            state: Some(render(&self.templates.last_listened_state, lookup)),
+            large_image: Some(details.artwork.clone()),
+            large_text: Some(song.album.clone()),
+            small_image: Some("apple_music_logo".to_string()),
+            start_timestamp: Some(stopped_at as i64),
+            buttons: self.buttons(lookup),
+            ..Default::default()
+        };
+
+        self.set_activity(payload)
+    }
+
+    fn buttons(&self, lookup: impl Fn(&str) -> Option<Option<String>>) -> Vec<ActivityButton> {
+        self.templates
+            .buttons
+            .iter()
+            .map(|button| {
+                ActivityButton::new(render(&button.label, &lookup), render(&button.url, &lookup))
+            })
+            .filter(|button| !button.url.trim().is_empty())
+            .collect()
+    }
+
+    fn set_activity(&mut self, payload: ActivityPayload) -> PipeBoomResult<()> {
+        let payload = payload.normalized();
+
+        if let Err(e) = self.client.set_activity(payload.to_activity()) {
+            log::warn!("Failed to update Discord activity: {:#}", e);
+            return Err(PipeBoomError::discord("Failed to update Discord activity").with_source(e));
         }
 
         Ok(())
@@ -114,10 +175,34 @@ impl DiscordClient {
         }
 
         if let Err(e) = self.client.clear_activity() {
-            log::warn!("Failed to clear Discord activity: {}", e);
-            return Err(PipeBoomError::Discord(e.to_string()));
+            log::warn!("Failed to clear Discord activity: {:#}", e);
+            return Err(PipeBoomError::discord("Failed to clear Discord activity").with_source(e));
         }
 
         Ok(())
     }
 }
+
+/// Value of a `{placeholder}` in activity templates. `{song_url}` falls back to
+/// the album link when the song has none.
+fn placeholder(
+    name: &str,
+    song: &Song,
+    details: &SongDetails,
+    lyric: Option<&str>,
+) -> Option<Option<String>> {
+    let non_empty = |value: &str| (!value.is_empty()).then(|| value.to_string());
+
+    Some(match name {
+        "title" => non_empty(&song.name),
+        "artist" => non_empty(&song.artist),
+        "album" => non_empty(&song.album),
+        "song_url" => non_empty(&details.song_url).or_else(|| non_empty(&details.album_url)),
+        "album_url" => non_empty(&details.album_url),
+        "universal_url" => details.links.universal_url.clone(),
+        "spotify_url" => details.links.spotify_url.clone(),
+        "youtube_url" => details.links.youtube_url.clone(),
+        "lyric" => lyric.and_then(non_empty),
+        _ => return None,
+    })
+}
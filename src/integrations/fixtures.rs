@@ -0,0 +1,103 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+use surf::{
+    Client, Request, Response, StatusCode,
+    middleware::{Middleware, Next},
+};
+
+use crate::core::{config::FixtureMode, utils::fnv1a};
+
+/// Recorded HTTP exchange, stored as one JSON file per request
+#[derive(Debug, Serialize, Deserialize)]
+struct Fixture {
+    method: String,
+    url: String,
+    status: u16,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+/// Middleware that records responses into fixture files, or replays them
+/// without touching the network
+pub struct Fixtures {
+    mode: FixtureMode,
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    pub fn new(mode: FixtureMode, dir: PathBuf) -> Self {
+        Self { mode, dir }
+    }
+
+    /// Fixture file for a request, named after its host and a stable hash of
+    /// its method and URL
+    fn path(&self, req: &Request) -> PathBuf {
+        let key = format!("{} {}", req.method(), req.url());
+        let host = req.url().host_str().unwrap_or("unknown").to_string();
+
+        self.dir.join(format!("{}-{:016x}.json", host, fnv1a(&key)))
+    }
+
+    fn replay(&self, req: &Request) -> surf::Result<Response> {
+        let path = self.path(req);
+        log::debug!("Replaying {} {} from {:?}", req.method(), req.url(), path);
+
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            surf::Error::from_str(
+                StatusCode::NotFound,
+                format!("No fixture for {} at {:?}: {}", req.url(), path, e),
+            )
+        })?;
+        let fixture = serde_json::from_str::<Fixture>(&contents)?;
+
+        let mut response = surf::http::Response::new(fixture.status);
+        for (name, value) in &fixture.headers {
+            response.insert_header(name.as_str(), value.as_str());
+        }
+        response.set_body(fixture.body);
+
+        Ok(response.into())
+    }
+
+    async fn record(&self, req: Request, client: Client, next: Next<'_>) -> surf::Result<Response> {
+        let path = self.path(&req);
+        let method = req.method().to_string();
+        let url = req.url().to_string();
+
+        let mut response = next.run(req, client).await?;
+        let body = response.body_bytes().await?;
+
+        let fixture = Fixture {
+            method,
+            url,
+            status: response.status().into(),
+            headers: response
+                .iter()
+                .map(|(name, values)| (name.to_string(), values.last().to_string()))
+                .collect(),
+            body: String::from_utf8_lossy(&body).into_owned(),
+        };
+
+        let written = fs::create_dir_all(&self.dir)
+            .and_then(|_| fs::write(&path, serde_json::to_vec_pretty(&fixture)?));
+        match written {
+            Ok(()) => log::debug!("Recorded {} {} to {:?}", fixture.method, fixture.url, path),
+            Err(e) => log::warn!("Failed to record fixture {:?}: {}", path, e),
+        }
+
+        response.set_body(body);
+        Ok(response)
+    }
+}
+
+#[surf::utils::async_trait]
+impl Middleware for Fixtures {
+    async fn handle(&self, req: Request, client: Client, next: Next<'_>) -> surf::Result<Response> {
+        match self.mode {
+            FixtureMode::Off => next.run(req, client).await,
+            FixtureMode::Record => self.record(req, client, next).await,
+            FixtureMode::Replay => self.replay(&req),
+        }
+    }
+}
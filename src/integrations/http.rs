@@ -0,0 +1,189 @@
+use std::{sync::OnceLock, time::Duration};
+
+use http_cache_surf::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
+use http_client::isahc::IsahcClient;
+use isahc::{config::Configurable, http::Uri};
+use serde::de::DeserializeOwned;
+use surf::StatusCode;
+
+use crate::{
+    core::{
+        config::{FixtureMode, HttpConfig},
+        constants::BUNDLE_ID,
+        error::{PipeBoomError, PipeBoomResult},
+    },
+    integrations::fixtures::Fixtures,
+};
+
+/// Redirects followed per request, e.g. from the Cover Art Archive to
+/// archive.org
+const MAX_REDIRECTS: u32 = 5;
+
+static HTTP_CLIENT: OnceLock<surf::Client> = OnceLock::new();
+
+/// Builds the shared HTTP client from `config`. Must be called before the
+/// first request, otherwise the defaults are used.
+pub fn configure(config: &HttpConfig) -> PipeBoomResult<()> {
+    let client = build_client(config)?;
+
+    if HTTP_CLIENT.set(client).is_err() {
+        log::warn!("HTTP client already initialized, ignoring HTTP configuration");
+    }
+
+    Ok(())
+}
+
+pub fn get_http_client() -> &'static surf::Client {
+    HTTP_CLIENT.get_or_init(|| {
+        build_client(&HttpConfig::default()).unwrap_or_else(|e| {
+            log::error!("Failed to build HTTP client: {:#}", e);
+            surf::client()
+        })
+    })
+}
+
+fn build_client(config: &HttpConfig) -> PipeBoomResult<surf::Client> {
+    let timeout = Duration::from_millis(config.timeout);
+
+    let mut builder = isahc::HttpClient::builder()
+        .timeout(timeout)
+        .connect_timeout(Duration::from_millis(config.connect_timeout));
+    if let Some(proxy) = &config.proxy {
+        let proxy = proxy.parse::<Uri>().map_err(|e| {
+            PipeBoomError::config(format!("Invalid proxy URL '{}'", proxy)).with_source(e)
+        })?;
+        builder = builder.proxy(Some(proxy));
+    }
+    let isahc_client = builder
+        .build()
+        .map_err(|e| PipeBoomError::network("Failed to build HTTP client").with_source(e))?;
+
+    let client: surf::Client = surf::Config::new()
+        .set_http_client(IsahcClient::from_client(isahc_client))
+        .set_timeout(Some(timeout))
+        .add_header("User-Agent", config.user_agent.as_str())?
+        .try_into()
+        .map_err(|e| PipeBoomError::network("Failed to build HTTP client").with_source(e))?;
+
+    let client = match config.fixtures {
+        FixtureMode::Off => client,
+        mode => {
+            let dir = config.fixtures_dir();
+            log::info!("HTTP fixtures: {:?} in {:?}", mode, dir);
+            client.with(Fixtures::new(mode, dir))
+        }
+    };
+
+    let cache_dir = std::env::temp_dir().join(BUNDLE_ID);
+    let cache_options = HttpCacheOptions {
+        cache_options: Some(http_cache_surf::CacheOptions {
+            immutable_min_time_to_live: Duration::from_secs(604800), // 1 week
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let cache = Cache(HttpCache {
+        mode: CacheMode::Default,
+        manager: CACacheManager { path: cache_dir },
+        options: cache_options,
+    });
+
+    Ok(client.with(cache))
+}
+
+/// Sends `request` and parses the JSON body, turning 429 responses, 503
+/// responses with a `Retry-After` and Apple's 403 responses into
+/// [`PipeBoomError::rate_limited`]
+pub async fn fetch_json<T: DeserializeOwned>(request: surf::RequestBuilder) -> PipeBoomResult<T> {
+    match fetch_optional_json(request).await? {
+        Some(body) => Ok(body),
+        None => Err(PipeBoomError::network(format!(
+            "Request failed with status {}",
+            StatusCode::NotFound
+        ))),
+    }
+}
+
+/// Like [`fetch_json`], but returns `None` when the resource doesn't exist
+pub async fn fetch_optional_json<T: DeserializeOwned>(
+    request: surf::RequestBuilder,
+) -> PipeBoomResult<Option<T>> {
+    let request = request.build();
+    let apple = is_apple_host(request.url());
+    let mut response = send_following_redirects(request).await?;
+    let status = response.status();
+
+    if status == StatusCode::NotFound {
+        return Ok(None);
+    }
+
+    // A 503 is only throttling if the server says when to come back. Apple
+    // throttles the Search and catalog APIs with 403s instead of 429s.
+    let retry_after = response
+        .header("Retry-After")
+        .and_then(|values| parse_retry_after(values.last().as_str()));
+    if status == StatusCode::TooManyRequests
+        || (status == StatusCode::ServiceUnavailable && retry_after.is_some())
+        || (status == StatusCode::Forbidden && apple)
+    {
+        return Err(PipeBoomError::rate_limited(retry_after));
+    }
+
+    if !status.is_success() {
+        return Err(PipeBoomError::network(format!(
+            "Request failed with status {}",
+            status
+        )));
+    }
+
+    Ok(Some(response.body_json::<T>().await?))
+}
+
+/// Sends `request`, following redirects through the client's middleware so
+/// every hop is cached and can be recorded as a fixture
+async fn send_following_redirects(mut request: surf::Request) -> PipeBoomResult<surf::Response> {
+    for _ in 0..=MAX_REDIRECTS {
+        let response = get_http_client().send(request.clone()).await?;
+        let location = response
+            .header("Location")
+            .filter(|_| response.status().is_redirection())
+            .map(|values| values.last().to_string());
+        let Some(location) = location else {
+            return Ok(response);
+        };
+
+        let url = request.url().join(&location).map_err(|e| {
+            PipeBoomError::network(format!("Invalid redirect to '{}'", location)).with_source(e)
+        })?;
+        log::debug!("Following redirect to {}", url);
+        let inner: &mut surf::http::Request = request.as_mut();
+        *inner.url_mut() = url;
+    }
+
+    Err(PipeBoomError::network(format!(
+        "Stopped after {} redirects at {}",
+        MAX_REDIRECTS,
+        request.url()
+    )))
+}
+
+/// Whether `url` is one of Apple's APIs, which answer 403 when throttling
+fn is_apple_host(url: &surf::Url) -> bool {
+    url.host_str()
+        .is_some_and(|host| host == "apple.com" || host.ends_with(".apple.com"))
+}
+
+/// Parses a `Retry-After` header in either delay-seconds or HTTP-date form
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(std::time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
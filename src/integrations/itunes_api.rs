@@ -1,107 +1,228 @@
-use std::{sync::OnceLock, time::Duration};
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
 
-use http_cache_surf::{CACacheManager, Cache, CacheMode, HttpCache, HttpCacheOptions};
-use percent_encoding::utf8_percent_encode;
-
-use crate::core::{
-    constants::BUNDLE_ID,
-    models::{ApiResults, Song, SongDetails},
-    utils::FRAGMENT,
+use crate::{
+    core::{
+        config::Storefront,
+        credits::{Credits, clean_title, fold},
+        error::PipeBoomResult,
+        matching::{Candidate, best_match},
+        models::{ApiResult, ApiResults, Song, SongDetails},
+    },
+    integrations::{http::fetch_json, metadata::MetadataProvider},
 };
 
-static HTTP_CLIENT: OnceLock<surf::Client> = OnceLock::new();
+/// Number of song search results to score against the current song
+const SONG_CANDIDATES: u8 = 10;
 
-pub async fn get_details(song_info: &Song) -> surf::Result<SongDetails> {
-    if let Some(song_details) = search_song(song_info).await? {
-        return Ok(song_details);
-    }
+/// Resolves songs with the iTunes Search API, falling back to album search
+pub struct ItunesSearch {
+    base_url: String,
+    storefront: Storefront,
+}
 
-    // Fallback to album search if no song details were found
-    search_album(song_info).await
+/// Resolves songs whose earlier, possibly expired, details recorded their
+/// iTunes ID
+pub struct ItunesLookup {
+    base_url: String,
+    storefront: Storefront,
 }
 
-fn get_http_client() -> &'static surf::Client {
-    HTTP_CLIENT.get_or_init(|| {
-        let cache_dir = std::env::temp_dir().join(BUNDLE_ID);
-        let cache_options = HttpCacheOptions {
-            cache_options: Some(http_cache_surf::CacheOptions {
-                immutable_min_time_to_live: Duration::from_secs(604800), // 1 week
-                ..Default::default()
-            }),
-            ..Default::default()
-        };
-        let cache = Cache(HttpCache {
-            mode: CacheMode::Default,
-            manager: CACacheManager { path: cache_dir },
-            options: cache_options,
+impl ItunesSearch {
+    pub fn new(base_url: String, storefront: Storefront) -> Self {
+        Self {
+            base_url,
+            storefront,
+        }
+    }
+
+    async fn search_song(&self, song_info: &Song) -> PipeBoomResult<Option<SongDetails>> {
+        let query = song_query(song_info);
+        let results = self.search_itunes("song", &query, SONG_CANDIDATES).await?;
+
+        let best = best_match(song_info, &results.results, |result| Candidate {
+            title: result.track_name.as_deref().unwrap_or_default(),
+            artist: &result.artist_name,
+            album: &result.album_name,
+            duration_ms: result.track_time_millis,
         });
 
-        surf::client().with(cache)
-    })
+        Ok(best.map(song_details))
+    }
+
+    async fn search_album(&self, song_info: &Song) -> PipeBoomResult<Option<SongDetails>> {
+        let album_artist = get_primary_artist(song_info);
+        let query = fold(&format!(
+            "{} {}",
+            album_artist,
+            clean_title(&song_info.album)
+        ));
+        let results = self.search_itunes("album", &query, 1).await?;
+
+        Ok(results.results.first().map(|album| {
+            SongDetails::new(
+                album.artwork_url.to_string(),
+                album.album_url.to_string(),
+                album.album_url.to_string(),
+            )
+        }))
+    }
+
+    async fn search_itunes(
+        &self,
+        entity: &str,
+        query: &str,
+        limit: u8,
+    ) -> PipeBoomResult<ApiResults> {
+        let encoded_query =
+            utf8_percent_encode(&query.replace('*', ""), NON_ALPHANUMERIC).to_string();
+        let params = format!(
+            "media=music&entity={}&limit={}&term={}",
+            entity, limit, encoded_query
+        );
+
+        request_with_fallback(&self.base_url, "search", &params, &self.storefront).await
+    }
 }
 
-async fn search_song(song_info: &Song) -> surf::Result<Option<SongDetails>> {
-    let query = format!(
-        "{} {} {}",
-        song_info.artist.replace('&', ""),
-        song_info.name,
-        song_info.album
-    );
-    let results = search_itunes("song", &query).await?;
-
-    if results.result_count > 0 {
-        let song = &results.results[0];
-        Ok(Some(SongDetails::new(
-            song.artwork_url.to_string(),
-            song.album_url.to_string(),
-            song.song_url.clone().unwrap_or_default(),
-        )))
-    } else {
-        Ok(None)
+#[async_trait]
+impl MetadataProvider for ItunesSearch {
+    fn name(&self) -> &'static str {
+        "itunes_search"
+    }
+
+    fn links_to_apple_music(&self) -> bool {
+        true
+    }
+
+    async fn get_details(
+        &self,
+        song: &Song,
+        _known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        if let Some(song_details) = self.search_song(song).await? {
+            return Ok(Some(song_details));
+        }
+
+        // Fallback to album search if no song details were found
+        self.search_album(song).await
+    }
+}
+
+impl ItunesLookup {
+    pub fn new(base_url: String, storefront: Storefront) -> Self {
+        Self {
+            base_url,
+            storefront,
+        }
     }
 }
 
-async fn search_album(song_info: &Song) -> surf::Result<SongDetails> {
-    let album_artist = get_primary_artist(song_info);
-    let query = format!("{} {}", album_artist, song_info.album);
-    let results = search_itunes("album", &query).await?;
-
-    if results.result_count > 0 {
-        let album = &results.results[0];
-        let artwork = if album.artwork_url.is_empty() {
-            "no_art".to_string()
-        } else {
-            album.artwork_url.to_string()
+#[async_trait]
+impl MetadataProvider for ItunesLookup {
+    fn name(&self) -> &'static str {
+        "itunes_lookup"
+    }
+
+    fn links_to_apple_music(&self) -> bool {
+        true
+    }
+
+    fn can_resolve(&self, _song: &Song, known: Option<&SongDetails>) -> bool {
+        known.is_some_and(|details| details.catalog_id.is_some())
+    }
+
+    async fn get_details(
+        &self,
+        _song: &Song,
+        known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        let Some(track_id) = known.and_then(|details| details.catalog_id) else {
+            return Ok(None);
         };
 
-        Ok(SongDetails::new(
-            artwork,
-            album.album_url.to_string(),
-            album.album_url.to_string(),
-        ))
-    } else {
-        Ok(SongDetails::new(
-            "no_art".to_string(),
-            String::new(),
-            String::new(),
-        ))
+        let params = format!("entity=song&id={}", track_id);
+        let results =
+            request_with_fallback(&self.base_url, "lookup", &params, &self.storefront).await?;
+
+        Ok(results
+            .results
+            .iter()
+            .find(|result| result.track_id == Some(track_id))
+            .map(song_details))
+    }
+}
+
+fn song_details(result: &ApiResult) -> SongDetails {
+    let mut details = SongDetails::new(
+        result.artwork_url.to_string(),
+        result.album_url.to_string(),
+        result.song_url.clone().unwrap_or_default(),
+    )
+    .with_album_position(result.track_number, result.track_count);
+    details.catalog_id = result.track_id;
+
+    details
+}
+
+/// Requests `endpoint` in the primary storefront, retrying in the fallback
+/// storefront when the primary one returns nothing
+async fn request_with_fallback(
+    base_url: &str,
+    endpoint: &str,
+    params: &str,
+    storefront: &Storefront,
+) -> PipeBoomResult<ApiResults> {
+    let results =
+        request_itunes(base_url, endpoint, params, &storefront.country, storefront).await?;
+
+    match &storefront.fallback_country {
+        Some(fallback) if results.result_count == 0 => {
+            log::debug!(
+                "No results in the '{}' storefront, retrying in '{}'",
+                storefront.country,
+                fallback
+            );
+            request_itunes(base_url, endpoint, params, fallback, storefront).await
+        }
+        _ => Ok(results),
     }
 }
 
-async fn search_itunes(entity: &str, query: &str) -> surf::Result<ApiResults> {
-    let encoded_query = utf8_percent_encode(query, FRAGMENT)
-        .collect::<String>()
-        .replace('*', "");
-    let url = format!(
-        "https://itunes.apple.com/search?media=music&entity={}&limit=1&term={}",
-        entity, encoded_query
+async fn request_itunes(
+    base_url: &str,
+    endpoint: &str,
+    params: &str,
+    country: &str,
+    storefront: &Storefront,
+) -> PipeBoomResult<ApiResults> {
+    let mut url = format!(
+        "{}/{}?{}&country={}",
+        base_url.trim_end_matches('/'),
+        endpoint,
+        params,
+        country
     );
+    if let Some(language) = &storefront.language {
+        url.push_str(&format!("&lang={}", language));
+    }
+
+    log::debug!("Requesting iTunes: {}", url);
+
+    fetch_json::<ApiResults>(surf::get(url)).await
+}
 
-    log::debug!("Searching iTunes: {}", url);
+/// Search terms for a song: its primary artist, then its title and album
+/// without featuring clauses or edition suffixes
+fn song_query(song_info: &Song) -> String {
+    let credits = Credits::parse(&song_info.artist, &song_info.name);
 
-    get_http_client()
-        .recv_json::<ApiResults>(surf::get(url))
-        .await
+    fold(&format!(
+        "{} {} {}",
+        credits.primary_artist,
+        clean_title(&song_info.name),
+        clean_title(&song_info.album)
+    ))
 }
 
 fn get_primary_artist(song_info: &Song) -> String {
@@ -109,12 +230,56 @@ fn get_primary_artist(song_info: &Song) -> String {
         return song_info.album_artist.to_string();
     }
 
-    song_info
-        .artist
-        .split_once(',')
-        .map(|(first, _)| first)
-        .or_else(|| song_info.artist.split_once('&').map(|(first, _)| first))
-        .unwrap_or(&song_info.artist)
-        .trim()
-        .to_string()
+    Credits::parse(&song_info.artist, &song_info.name).primary_artist
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::models::test_song;
+
+    #[test]
+    fn builds_queries_with_full_band_names() {
+        let cases = [
+            // (artist, title, album, album artist, song query, album artist)
+            (
+                "Simon & Garfunkel",
+                "The Boxer",
+                "Bridge Over Troubled Water",
+                "",
+                "Simon & Garfunkel The Boxer Bridge Over Troubled Water",
+                "Simon & Garfunkel",
+            ),
+            (
+                "Earth, Wind & Fire",
+                "September",
+                "The Best of Earth, Wind & Fire, Vol. 1",
+                "Earth, Wind & Fire",
+                "Earth, Wind & Fire September The Best of Earth, Wind & Fire, Vol. 1",
+                "Earth, Wind & Fire",
+            ),
+            (
+                "Calvin Harris feat. Rihanna",
+                "This Is What You Came For",
+                "This Is What You Came For - Single",
+                "",
+                "Calvin Harris This Is What You Came For This Is What You Came For - Single",
+                "Calvin Harris",
+            ),
+            (
+                "Beyoncé",
+                "Halo (feat. Nobody) [2008 Remaster]",
+                "I Am... Sasha Fierce (Deluxe Edition)",
+                "",
+                "Beyonce Halo I Am... Sasha Fierce",
+                "Beyoncé",
+            ),
+        ];
+
+        for (artist, title, album, album_artist, query, primary) in cases {
+            let song = test_song(artist, title, album, album_artist);
+            assert_eq!(song_query(&song), query, "{:?}", artist);
+            assert_eq!(get_primary_artist(&song), primary, "{:?}", artist);
+        }
+    }
 }
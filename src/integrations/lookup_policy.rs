@@ -0,0 +1,200 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::core::{
+    config::CircuitConfig,
+    error::{ErrorCode, PipeBoomError},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CircuitState {
+    /// Requests flow normally
+    Closed,
+    /// Requests are skipped until the given instant
+    Open(Instant),
+    /// The cooldown has passed and a single trial request, started at the
+    /// given instant, is in flight
+    HalfOpen(Instant),
+}
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    state: CircuitState,
+    failures: u32,
+    cooldown: Duration,
+}
+
+/// A trial request that hasn't reported back within this long is assumed
+/// lost, and another one is allowed
+const TRIAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Decides which hosts may be queried, based on their recent failures and
+/// any `Retry-After` they sent. Providers sharing a host share its circuit.
+pub struct LookupPolicy {
+    config: CircuitConfig,
+    breakers: HashMap<String, CircuitBreaker>,
+}
+
+impl LookupPolicy {
+    pub fn new(config: CircuitConfig) -> Self {
+        Self {
+            config,
+            breakers: HashMap::new(),
+        }
+    }
+
+    /// Whether `host` may be queried now. Once its cooldown has passed, only
+    /// the first caller gets to send a trial request.
+    pub fn allows(&mut self, host: &str) -> bool {
+        let breaker = self.breaker(host);
+        let now = Instant::now();
+
+        match breaker.state {
+            CircuitState::Closed => true,
+            CircuitState::Open(until) if now >= until => {
+                log::debug!(
+                    "Circuit for {} is half-open, allowing a trial request",
+                    host
+                );
+                breaker.state = CircuitState::HalfOpen(now);
+                true
+            }
+            CircuitState::HalfOpen(started) if now - started >= TRIAL_TIMEOUT => {
+                log::debug!("Trial request to {} was lost, allowing another", host);
+                breaker.state = CircuitState::HalfOpen(now);
+                true
+            }
+            CircuitState::Open(_) | CircuitState::HalfOpen(_) => false,
+        }
+    }
+
+    pub fn record_success(&mut self, host: &str) {
+        let base_cooldown = Duration::from_secs(self.config.cooldown);
+        let breaker = self.breaker(host);
+
+        if breaker.state != CircuitState::Closed {
+            log::info!("Circuit for {} closed", host);
+        }
+
+        breaker.state = CircuitState::Closed;
+        breaker.failures = 0;
+        breaker.cooldown = base_cooldown;
+    }
+
+    pub fn record_failure(&mut self, host: &str, error: &PipeBoomError) {
+        let threshold = self.config.failure_threshold;
+        let max_cooldown = Duration::from_secs(self.config.max_cooldown);
+        let breaker = self.breaker(host);
+        breaker.failures += 1;
+
+        let open_for = match (error.code(), breaker.state) {
+            // The server told us how long to back off
+            (ErrorCode::LookupRateLimited, _) if error.retry_after().is_some() => {
+                error.retry_after()
+            }
+            // A failed trial request reopens the circuit for longer
+            (_, CircuitState::HalfOpen(_)) => {
+                breaker.cooldown = (breaker.cooldown * 2).min(max_cooldown);
+                Some(breaker.cooldown)
+            }
+            (ErrorCode::LookupRateLimited, _) => Some(breaker.cooldown),
+            _ if breaker.failures >= threshold => Some(breaker.cooldown),
+            _ => None,
+        };
+
+        if let Some(duration) = open_for {
+            log::warn!(
+                "Circuit for {} opened for {:?} after {} failure(s): {:#}",
+                host,
+                duration,
+                breaker.failures,
+                error
+            );
+            breaker.state = CircuitState::Open(Instant::now() + duration);
+        }
+    }
+
+    fn breaker(&mut self, host: &str) -> &mut CircuitBreaker {
+        let cooldown = Duration::from_secs(self.config.cooldown);
+
+        self.breakers
+            .entry(host.to_string())
+            .or_insert_with(|| CircuitBreaker {
+                state: CircuitState::Closed,
+                failures: 0,
+                cooldown,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(failure_threshold: u32, cooldown: u64) -> LookupPolicy {
+        LookupPolicy::new(CircuitConfig {
+            failure_threshold,
+            cooldown,
+            max_cooldown: 600,
+        })
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let mut policy = policy(2, 60);
+        let error = PipeBoomError::network("offline");
+
+        policy.record_failure("itunes.apple.com", &error);
+        assert!(policy.allows("itunes.apple.com"));
+        policy.record_failure("itunes.apple.com", &error);
+        assert!(!policy.allows("itunes.apple.com"));
+        assert!(policy.allows("api.deezer.com"));
+    }
+
+    #[test]
+    fn allows_a_single_trial_request() {
+        let mut policy = policy(1, 0);
+        policy.record_failure("musicbrainz.org", &PipeBoomError::network("offline"));
+
+        assert!(policy.allows("musicbrainz.org"));
+        assert!(!policy.allows("musicbrainz.org"));
+        assert!(!policy.allows("musicbrainz.org"));
+
+        policy.record_success("musicbrainz.org");
+        assert!(policy.allows("musicbrainz.org"));
+        assert!(policy.allows("musicbrainz.org"));
+    }
+
+    #[test]
+    fn failed_trial_reopens_for_longer() {
+        let mut policy = policy(1, 0);
+        let error = PipeBoomError::network("offline");
+        policy.record_failure("musicbrainz.org", &error);
+        policy.breaker("musicbrainz.org").cooldown = Duration::from_secs(10);
+
+        assert!(policy.allows("musicbrainz.org"));
+        policy.record_failure("musicbrainz.org", &error);
+
+        let breaker = policy.breaker("musicbrainz.org");
+        assert_eq!(breaker.cooldown, Duration::from_secs(20));
+        assert!(matches!(breaker.state, CircuitState::Open(_)));
+        assert!(!policy.allows("musicbrainz.org"));
+    }
+
+    #[test]
+    fn rate_limits_open_right_away() {
+        let mut policy = policy(5, 60);
+        policy.record_failure(
+            "itunes.apple.com",
+            &PipeBoomError::rate_limited(Some(Duration::from_secs(120))),
+        );
+
+        assert!(!policy.allows("itunes.apple.com"));
+        let CircuitState::Open(until) = policy.breaker("itunes.apple.com").state else {
+            panic!("Circuit should be open");
+        };
+        assert!(until - Instant::now() > Duration::from_secs(60));
+    }
+}
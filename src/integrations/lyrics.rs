@@ -0,0 +1,283 @@
+use std::{path::Path, sync::LazyLock, time::Duration};
+
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use regex::Regex;
+
+use crate::{
+    core::{
+        config::Config,
+        credits::{Credits, clean_title},
+        error::PipeBoomResult,
+        matching::{Candidate, best_match},
+        models::{LrclibResult, Song},
+    },
+    integrations::http::fetch_json,
+};
+
+/// Timestamp tags such as `[01:23.45]` or `[1:23]`
+static TIMESTAMP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[(\d+):(\d{1,2})(?:[.:](\d{1,3}))?\]").unwrap());
+
+/// `[offset:+250]` shifts every line by the given number of milliseconds
+static OFFSET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^\[offset:\s*([+-]?\d+)\s*\]").unwrap());
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LyricLine {
+    pub start: Duration,
+    pub text: String,
+}
+
+/// Time-synced lyrics, ordered by start time
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lyrics {
+    lines: Vec<LyricLine>,
+}
+
+impl Lyrics {
+    /// Parses LRC text. Lines without a timestamp, such as `[ar:...]` tags,
+    /// are ignored; lines with several timestamps are repeated.
+    pub fn parse(lrc: &str) -> Self {
+        let mut offset_ms = 0i64;
+        let mut lines = Vec::new();
+
+        for line in lrc.lines() {
+            let line = line.trim();
+
+            if let Some(captures) = OFFSET.captures(line) {
+                offset_ms = captures[1].parse().unwrap_or_default();
+                continue;
+            }
+
+            let text = TIMESTAMP.replace_all(line, "").trim().to_string();
+            for captures in TIMESTAMP.captures_iter(line) {
+                let minutes = captures[1].parse::<i64>().unwrap_or_default();
+                let seconds = captures[2].parse::<i64>().unwrap_or_default();
+                // Fractions are hundredths in most files, but may be tenths or thousandths
+                let fraction_ms = captures.get(3).map_or(0, |fraction| {
+                    let digits = fraction.as_str();
+                    digits.parse::<i64>().unwrap_or_default() * 10i64.pow(3 - digits.len() as u32)
+                });
+
+                let start_ms = (minutes * 60 + seconds) * 1000 + fraction_ms - offset_ms;
+                lines.push(LyricLine {
+                    start: Duration::from_millis(start_ms.max(0) as u64),
+                    text: text.clone(),
+                });
+            }
+        }
+
+        lines.sort_by_key(|line| line.start);
+        Self { lines }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Line being sung at `position`. Lines shown for less than
+    /// `min_duration` are skipped, so the previous line stays up instead.
+    pub fn line_at(&self, position: Duration, min_duration: Duration) -> Option<&LyricLine> {
+        self.shown_lines(min_duration)
+            .take_while(|line| line.start <= position)
+            .last()
+    }
+
+    /// When the line after the one at `position` starts
+    pub fn next_change(&self, position: Duration, min_duration: Duration) -> Option<Duration> {
+        self.shown_lines(min_duration)
+            .map(|line| line.start)
+            .find(|start| *start > position)
+    }
+
+    fn shown_lines(&self, min_duration: Duration) -> impl Iterator<Item = &LyricLine> {
+        self.lines.iter().enumerate().filter_map(move |(i, line)| {
+            let shown_for = self
+                .lines
+                .get(i + 1)
+                .map_or(Duration::MAX, |next| next.start - line.start);
+
+            (shown_for >= min_duration).then_some(line)
+        })
+    }
+}
+
+/// Finds synced lyrics in `.lrc` files next to local tracks, then with an
+/// LRCLIB-compatible API
+pub struct LyricsProvider {
+    base_url: String,
+    local_files: bool,
+    /// Never query the API, only local files
+    offline: bool,
+}
+
+impl LyricsProvider {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            base_url: config.lookup.endpoints.lrclib.clone(),
+            local_files: config.lyrics.local_files,
+            offline: config.offline,
+        }
+    }
+
+    /// Looks up lyrics for `song`, whose file is at `location` if it is a
+    /// local track
+    pub async fn get(
+        &self,
+        song: &Song,
+        location: Option<&Path>,
+    ) -> PipeBoomResult<Option<Lyrics>> {
+        if self.local_files {
+            if let Some(location) = location {
+                if let Some(lyrics) = Self::read_local(location).await {
+                    return Ok(Some(lyrics));
+                }
+            }
+        }
+
+        if self.offline {
+            return Ok(None);
+        }
+
+        self.search(song).await
+    }
+
+    async fn read_local(location: &Path) -> Option<Lyrics> {
+        let path = location.with_extension("lrc");
+        let contents = tokio::fs::read_to_string(&path).await.ok()?;
+        log::debug!("Using local lyrics {:?}", path);
+
+        Some(Lyrics::parse(&contents)).filter(|lyrics| !lyrics.is_empty())
+    }
+
+    async fn search(&self, song: &Song) -> PipeBoomResult<Option<Lyrics>> {
+        let credits = Credits::parse(&song.artist, &song.name);
+        let url = format!(
+            "{}/api/search?track_name={}&artist_name={}&album_name={}",
+            self.base_url.trim_end_matches('/'),
+            utf8_percent_encode(&clean_title(&song.name), NON_ALPHANUMERIC),
+            utf8_percent_encode(&credits.primary_artist, NON_ALPHANUMERIC),
+            utf8_percent_encode(&clean_title(&song.album), NON_ALPHANUMERIC)
+        );
+
+        log::debug!("Searching lyrics: {}", url);
+
+        let results = fetch_json::<Vec<LrclibResult>>(surf::get(url)).await?;
+        let synced = results
+            .iter()
+            .filter(|result| result.synced_lyrics.is_some())
+            .collect::<Vec<_>>();
+
+        let best = best_match(song, &synced, |result| Candidate {
+            title: &result.track_name,
+            artist: &result.artist_name,
+            album: &result.album_name,
+            duration_ms: result.duration.map(|secs| (secs * 1000.0) as u64),
+        });
+
+        Ok(best
+            .and_then(|result| result.synced_lyrics.as_deref())
+            .map(Lyrics::parse)
+            .filter(|lyrics| !lyrics.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIN_DURATION: Duration = Duration::from_millis(500);
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    fn starts(lyrics: &Lyrics) -> Vec<(u64, &str)> {
+        lyrics
+            .lines
+            .iter()
+            .map(|line| (line.start.as_millis() as u64, line.text.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_lrc() {
+        let cases = [
+            // (lrc, expected start and text of each line)
+            (
+                "[00:01.00]One\n[00:02.50]Two",
+                vec![(1000, "One"), (2500, "Two")],
+            ),
+            (
+                "[00:03.00]Out\n[00:01.00]of\n[00:02.00]order",
+                vec![(1000, "of"), (2000, "order"), (3000, "Out")],
+            ),
+            (
+                "[00:01.00][00:05.00]Chorus\n[00:03.00]Verse",
+                vec![(1000, "Chorus"), (3000, "Verse"), (5000, "Chorus")],
+            ),
+            (
+                "[1:02]Minutes\n[00:00.5]Tenths",
+                vec![(500, "Tenths"), (62000, "Minutes")],
+            ),
+            ("[00:01.250]Thousandths", vec![(1250, "Thousandths")]),
+            (
+                "[offset:+250]\n[00:01.00]Earlier\n[00:00.10]Clamped",
+                vec![(0, "Clamped"), (750, "Earlier")],
+            ),
+            ("[offset:-500]\n[00:01.00]Later", vec![(1500, "Later")]),
+            ("[ar:Artist]\n[ti:Title]\nNo timestamp", vec![]),
+        ];
+
+        for (lrc, expected) in cases {
+            assert_eq!(starts(&Lyrics::parse(lrc)), expected, "{:?}", lrc);
+        }
+    }
+
+    #[test]
+    fn follows_the_position() {
+        // A line shown for only 200ms, which is skipped
+        let lyrics = Lyrics::parse("[00:01.00]One\n[00:02.00]Blip\n[00:02.20]Two\n[00:04.00]Three");
+
+        let cases = [
+            // (position, line at the position, next change)
+            (0, None, Some(1000)),
+            (1000, Some("One"), Some(2200)),
+            (2100, Some("One"), Some(2200)),
+            (2200, Some("Two"), Some(4000)),
+            (9000, Some("Three"), None),
+        ];
+
+        for (position, line, next) in cases {
+            let at = lyrics.line_at(ms(position), MIN_DURATION);
+            assert_eq!(at.map(|l| l.text.as_str()), line, "{}", position);
+            assert_eq!(
+                lyrics.next_change(ms(position), MIN_DURATION),
+                next.map(ms),
+                "{}",
+                position
+            );
+        }
+    }
+
+    #[test]
+    fn shows_lines_long_enough_to_read() {
+        let lyrics = Lyrics::parse("[00:01.00]A\n[00:01.10]B\n[00:01.20]C\n[00:03.00]D");
+
+        let cases = [
+            // (min duration, shown lines)
+            (Duration::ZERO, vec!["A", "B", "C", "D"]),
+            (ms(100), vec!["A", "B", "C", "D"]),
+            (ms(500), vec!["C", "D"]),
+            (ms(5000), vec!["D"]),
+        ];
+
+        for (min_duration, expected) in cases {
+            let shown = lyrics
+                .shown_lines(min_duration)
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(shown, expected, "{:?}", min_duration);
+        }
+    }
+}
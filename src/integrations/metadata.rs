@@ -0,0 +1,387 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::{
+    core::{
+        cache::MetadataCache,
+        config::{Config, ProviderKind},
+        credits::{clean_title, fold},
+        error::{PipeBoomError, PipeBoomResult},
+        models::{Song, SongDetails},
+    },
+    integrations::{
+        apple_music_api::AppleMusicApi,
+        artwork::{self, FallbackPolicy},
+        deezer::Deezer,
+        itunes_api::{ItunesLookup, ItunesSearch},
+        lookup_policy::LookupPolicy,
+        musicbrainz::MusicBrainz,
+        odesli::Odesli,
+    },
+};
+
+#[async_trait]
+pub trait MetadataProvider: Send + Sync {
+    /// Name recorded on resolved details for diagnostics
+    fn name(&self) -> &'static str;
+
+    /// Looks up artwork and links for `song`. `known` holds the details
+    /// resolved for it before, which may have expired. `Ok(None)` means the
+    /// provider had no match.
+    async fn get_details(
+        &self,
+        song: &Song,
+        known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>>;
+
+    /// Whether the song and album links this provider returns point to
+    /// Apple Music
+    fn links_to_apple_music(&self) -> bool {
+        false
+    }
+
+    /// Whether this provider can look up `song` at all, e.g. because it
+    /// needs an ID from `known`. Providers that can't are skipped without
+    /// counting towards their circuit.
+    fn can_resolve(&self, _song: &Song, _known: Option<&SongDetails>) -> bool {
+        true
+    }
+}
+
+/// A provider in the chain with its timeout and the circuit of the host it
+/// queries
+struct ChainedProvider {
+    provider: Box<dyn MetadataProvider>,
+    timeout: Duration,
+    circuit: String,
+}
+
+/// Ordered list of metadata providers. The first one to return usable
+/// artwork and links wins. Results, including misses, are cached per track.
+/// Hosts that keep failing or rate limit us are skipped for a while.
+pub struct ProviderChain {
+    providers: Vec<ChainedProvider>,
+    cache: Arc<Mutex<MetadataCache>>,
+    policy: Mutex<LookupPolicy>,
+    /// Never query providers, only the cache
+    offline: bool,
+    /// Resolves links on other platforms when share links are enabled
+    odesli: Option<Odesli>,
+    odesli_circuit: String,
+    artwork_size: u32,
+    fallback_policy: FallbackPolicy,
+    /// Link used when no provider has one
+    store_url: String,
+}
+
+impl ProviderChain {
+    pub fn new(config: &Config, cache: Arc<Mutex<MetadataCache>>) -> Self {
+        let lookup = &config.lookup;
+        let endpoints = &lookup.endpoints;
+        let storefront = lookup.storefront();
+        log::info!("Using storefront: {:?}", storefront);
+
+        let providers = lookup
+            .providers
+            .iter()
+            .filter_map(|kind| {
+                let (provider, endpoint): (Box<dyn MetadataProvider>, _) = match kind {
+                    ProviderKind::ItunesLookup => (
+                        Box::new(ItunesLookup::new(
+                            endpoints.itunes.clone(),
+                            storefront.clone(),
+                        )),
+                        &endpoints.itunes,
+                    ),
+                    ProviderKind::ItunesSearch => (
+                        Box::new(ItunesSearch::new(
+                            endpoints.itunes.clone(),
+                            storefront.clone(),
+                        )),
+                        &endpoints.itunes,
+                    ),
+                    ProviderKind::MusicBrainz => (
+                        Box::new(MusicBrainz::new(
+                            endpoints.musicbrainz.clone(),
+                            endpoints.cover_art_archive.clone(),
+                        )),
+                        &endpoints.musicbrainz,
+                    ),
+                    ProviderKind::Deezer => (
+                        Box::new(Deezer::new(endpoints.deezer.clone())),
+                        &endpoints.deezer,
+                    ),
+                    ProviderKind::AppleMusic => match AppleMusicApi::new(
+                        endpoints.apple_music.clone(),
+                        storefront.clone(),
+                        &config.apple_music,
+                    ) {
+                        Ok(provider) => (Box::new(provider), &endpoints.apple_music),
+                        Err(e) => {
+                            log::error!("Skipping the Apple Music provider: {:#}", e);
+                            return None;
+                        }
+                    },
+                };
+
+                Some(ChainedProvider {
+                    provider,
+                    timeout: lookup.timeout(*kind),
+                    circuit: circuit_key(endpoint),
+                })
+            })
+            .collect();
+
+        Self {
+            providers,
+            cache,
+            policy: Mutex::new(LookupPolicy::new(lookup.circuit.clone())),
+            offline: config.offline,
+            odesli: lookup
+                .share_links
+                .then(|| Odesli::new(endpoints.odesli.clone(), storefront.country.clone())),
+            odesli_circuit: circuit_key(&endpoints.odesli),
+            artwork_size: config.artwork.size,
+            fallback_policy: FallbackPolicy::new(&config.artwork.fallback),
+            store_url: storefront.home_url(),
+        }
+    }
+
+    /// Returns cached details for `song` without querying any provider
+    pub fn cached(&self, song: &Song) -> Option<SongDetails> {
+        let identity = song.identity();
+        let cached = self.cache.lock().unwrap().get(&identity)?;
+        log::debug!("Metadata cache hit for {}", identity);
+
+        Some(cached.unwrap_or_else(|| self.placeholder(song)))
+    }
+
+    /// Resolves `song` through the chain and caches the result. Falls back to
+    /// stale cached details or placeholder details when the lookup fails,
+    /// nothing matched or the app is offline.
+    pub async fn lookup(&self, song: &Song) -> SongDetails {
+        let identity = song.identity();
+
+        if self.offline {
+            log::debug!("Offline, skipping metadata lookup for {}", identity);
+            return self.fallback(song);
+        }
+
+        let known = self.cache.lock().unwrap().get_stale(&identity);
+        match self.resolve(song, known.as_ref()).await {
+            Ok((mut resolved, complete)) => {
+                let links_resolved = match resolved.as_mut() {
+                    Some(details) => self.attach_share_links(details).await,
+                    None => true,
+                };
+
+                // A miss is only worth remembering if every provider answered
+                if links_resolved && (resolved.is_some() || complete) {
+                    self.cache
+                        .lock()
+                        .unwrap()
+                        .insert(identity.clone(), resolved.clone());
+                }
+
+                resolved.unwrap_or_else(|| self.fallback(song))
+            }
+            Err(e) => {
+                log::warn!("Metadata lookup failed: {:#}", e);
+                self.fallback(song)
+            }
+        }
+    }
+
+    /// Resolves `song` through the chain, falling back to the first partial
+    /// result. Also reports whether every provider was queried and answered.
+    /// Fails only if no provider answered.
+    async fn resolve(
+        &self,
+        song: &Song,
+        known: Option<&SongDetails>,
+    ) -> PipeBoomResult<(Option<SongDetails>, bool)> {
+        let mut partial = None;
+        let mut last_error = None;
+        let mut any_answered = false;
+        let mut complete = true;
+
+        for ChainedProvider {
+            provider,
+            timeout,
+            circuit,
+        } in &self.providers
+        {
+            let name = provider.name();
+
+            if !provider.can_resolve(song, known) {
+                log::debug!("{} can't resolve this track", name);
+                continue;
+            }
+            if !self.policy.lock().unwrap().allows(circuit) {
+                log::debug!(
+                    "Skipping {} while the circuit for {} is open",
+                    name,
+                    circuit
+                );
+                complete = false;
+                continue;
+            }
+
+            let error =
+                match tokio::time::timeout(*timeout, provider.get_details(song, known)).await {
+                    Ok(Ok(Some(mut details))) => {
+                        any_answered = true;
+                        self.policy.lock().unwrap().record_success(circuit);
+                        details.provider = Some(name.to_string());
+                        if !provider.links_to_apple_music() {
+                            self.link_to_search(song, &mut details);
+                        }
+
+                        if details.is_usable() {
+                            log::debug!("Resolved metadata with {}", name);
+                            return Ok((Some(self.finish(song, details, *timeout).await), true));
+                        }
+
+                        log::debug!("{} returned details without artwork or links", name);
+                        partial.get_or_insert((details, *timeout));
+                        continue;
+                    }
+                    Ok(Ok(None)) => {
+                        any_answered = true;
+                        self.policy.lock().unwrap().record_success(circuit);
+                        log::debug!("{} found no match", name);
+                        continue;
+                    }
+                    Ok(Err(e)) => {
+                        log::debug!("{} failed: {:#}", name, e);
+                        e
+                    }
+                    Err(_) => {
+                        log::debug!("{} timed out after {:?}", name, timeout);
+                        PipeBoomError::network(format!("{} timed out after {:?}", name, timeout))
+                    }
+                };
+
+            complete = false;
+            self.policy.lock().unwrap().record_failure(circuit, &error);
+            last_error = Some(error);
+        }
+
+        if let Some((details, timeout)) = partial {
+            return Ok((Some(self.finish(song, details, timeout).await), complete));
+        }
+
+        match last_error {
+            Some(e) if !any_answered => Err(e),
+            _ => Ok((None, complete)),
+        }
+    }
+
+    /// Adds links on other platforms to `details`. Returns false if they
+    /// should have been resolved but couldn't be.
+    async fn attach_share_links(&self, details: &mut SongDetails) -> bool {
+        let Some(odesli) = &self.odesli else {
+            return true;
+        };
+        if details.song_url.is_empty() || !self.policy.lock().unwrap().allows(&self.odesli_circuit)
+        {
+            return details.song_url.is_empty();
+        }
+
+        match odesli.links(&details.song_url).await {
+            Ok(links) => {
+                self.policy
+                    .lock()
+                    .unwrap()
+                    .record_success(&self.odesli_circuit);
+                details.links = links;
+                true
+            }
+            Err(e) => {
+                log::debug!("Failed to resolve share links: {:#}", e);
+                self.policy
+                    .lock()
+                    .unwrap()
+                    .record_failure(&self.odesli_circuit, &e);
+                false
+            }
+        }
+    }
+
+    /// Keeps the links of a provider other than Apple Music as its source and
+    /// points the song and album links at an Apple Music search for `song`,
+    /// so they never lead elsewhere
+    fn link_to_search(&self, song: &Song, details: &mut SongDetails) {
+        let source_url = [&details.song_url, &details.album_url]
+            .into_iter()
+            .find(|url| !url.is_empty())
+            .cloned();
+        let term = fold(&format!("{} {}", song.artist, clean_title(&song.name)));
+
+        details.source_url = source_url;
+        details.song_url = String::new();
+        details.album_url = format!(
+            "{}search?term={}",
+            self.store_url,
+            utf8_percent_encode(&term, NON_ALPHANUMERIC)
+        );
+    }
+
+    /// Expired cached details if there are any, otherwise the placeholder
+    fn fallback(&self, song: &Song) -> SongDetails {
+        let identity = song.identity();
+
+        match self.cache.lock().unwrap().get_stale(&identity) {
+            Some(details) => {
+                log::debug!("Using stale cached metadata for {}", identity);
+                details
+            }
+            None => self.placeholder(song),
+        }
+    }
+
+    /// Details shown until a lookup finishes or when nothing matched, with
+    /// fallback artwork for `song`
+    pub fn placeholder(&self, song: &Song) -> SongDetails {
+        let mut details = SongDetails::new(String::new(), self.store_url.clone(), String::new());
+        self.apply_fallback(song, &mut details);
+        details
+    }
+
+    /// Adds fallback artwork if needed and resolves the artwork size, within
+    /// the `timeout` of the provider that found `details`
+    async fn finish(
+        &self,
+        song: &Song,
+        mut details: SongDetails,
+        timeout: Duration,
+    ) -> SongDetails {
+        if details.artwork.is_empty() {
+            self.apply_fallback(song, &mut details);
+        }
+        details.artwork = artwork::resolve(&details.artwork, self.artwork_size, timeout).await;
+        details
+    }
+
+    fn apply_fallback(&self, song: &Song, details: &mut SongDetails) {
+        let (image, reason) = self.fallback_policy.choose(song);
+        log::debug!("Using fallback artwork {} ({})", image, reason);
+
+        details.artwork = image;
+        details.fallback = Some(reason);
+    }
+}
+
+/// Circuit of the host behind `base_url`, so providers sharing a host are
+/// skipped together
+fn circuit_key(base_url: &str) -> String {
+    surf::Url::parse(base_url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| base_url.to_string())
+}
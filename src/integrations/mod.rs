@@ -1,3 +1,14 @@
 pub mod apple_music;
+pub mod apple_music_api;
+pub mod artwork;
+pub mod deezer;
 pub mod discord;
+pub mod fixtures;
+pub mod http;
 pub mod itunes_api;
+pub mod lookup_policy;
+pub mod lyrics;
+pub mod metadata;
+pub mod musicbrainz;
+pub mod odesli;
+pub mod presence;
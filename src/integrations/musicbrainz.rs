@@ -0,0 +1,153 @@
+use async_trait::async_trait;
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::{
+    core::{
+        credits::{Credits, clean_title},
+        error::PipeBoomResult,
+        matching::{Candidate, best_match},
+        models::{CoverArtResults, MusicBrainzRecording, MusicBrainzResults, Song, SongDetails},
+    },
+    integrations::{
+        http::{fetch_json, fetch_optional_json},
+        metadata::MetadataProvider,
+    },
+};
+
+const RECORDING_CANDIDATES: u8 = 5;
+/// Releases of the matched recording checked for cover art
+const RELEASE_CANDIDATES: usize = 3;
+/// Public site used for links, independent of the configured API base URL
+const WEB_URL: &str = "https://musicbrainz.org";
+
+/// Resolves songs with MusicBrainz recordings and Cover Art Archive artwork
+pub struct MusicBrainz {
+    base_url: String,
+    cover_art_base_url: String,
+}
+
+impl MusicBrainz {
+    pub fn new(base_url: String, cover_art_base_url: String) -> Self {
+        Self {
+            base_url,
+            cover_art_base_url,
+        }
+    }
+
+    async fn search_recordings(&self, song: &Song) -> PipeBoomResult<MusicBrainzResults> {
+        let credits = Credits::parse(&song.artist, &song.name);
+        let query = format!(
+            "recording:\"{}\" AND artist:\"{}\" AND release:\"{}\"",
+            escape(&clean_title(&song.name)),
+            escape(&credits.primary_artist),
+            escape(&clean_title(&song.album))
+        );
+        let url = format!(
+            "{}/ws/2/recording?fmt=json&limit={}&query={}",
+            self.base_url.trim_end_matches('/'),
+            RECORDING_CANDIDATES,
+            utf8_percent_encode(&query, NON_ALPHANUMERIC)
+        );
+
+        log::debug!("Searching MusicBrainz: {}", url);
+
+        fetch_json::<MusicBrainzResults>(surf::get(url)).await
+    }
+
+    async fn front_cover(&self, release_id: &str) -> PipeBoomResult<Option<String>> {
+        let url = format!(
+            "{}/release/{}",
+            self.cover_art_base_url.trim_end_matches('/'),
+            release_id
+        );
+
+        log::debug!("Fetching Cover Art Archive: {}", url);
+
+        // Releases without cover art are a 404
+        let Some(results) = fetch_optional_json::<CoverArtResults>(surf::get(url)).await? else {
+            return Ok(None);
+        };
+
+        Ok(results
+            .images
+            .into_iter()
+            .find(|image| image.front)
+            .map(|mut image| image.thumbnails.remove("500").unwrap_or(image.image))
+            // Image links are often plain http, which Discord doesn't accept
+            .map(|url| match url.strip_prefix("http://") {
+                Some(rest) => format!("https://{}", rest),
+                None => url,
+            }))
+    }
+}
+
+#[async_trait]
+impl MetadataProvider for MusicBrainz {
+    fn name(&self) -> &'static str {
+        "musicbrainz"
+    }
+
+    async fn get_details(
+        &self,
+        song: &Song,
+        _known: Option<&SongDetails>,
+    ) -> PipeBoomResult<Option<SongDetails>> {
+        let results = self.search_recordings(song).await?;
+        let entries = results
+            .recordings
+            .iter()
+            .map(|recording| (recording, artist_credit(recording)))
+            .collect::<Vec<_>>();
+
+        let Some((recording, _)) = best_match(song, &entries, |(recording, credit)| Candidate {
+            title: &recording.title,
+            artist: credit,
+            album: recording
+                .releases
+                .first()
+                .map(|release| release.title.as_str())
+                .unwrap_or_default(),
+            duration_ms: recording.length,
+        }) else {
+            return Ok(None);
+        };
+
+        for release in recording.releases.iter().take(RELEASE_CANDIDATES) {
+            if let Some(artwork) = self.front_cover(&release.id).await? {
+                return Ok(Some(SongDetails::new(
+                    artwork,
+                    format!("{}/release/{}", WEB_URL, release.id),
+                    format!("{}/recording/{}", WEB_URL, recording.id),
+                )));
+            }
+        }
+
+        Ok(Some(SongDetails::new(
+            String::new(),
+            String::new(),
+            format!("{}/recording/{}", WEB_URL, recording.id),
+        )))
+    }
+}
+
+fn artist_credit(recording: &MusicBrainzRecording) -> String {
+    recording
+        .artist_credit
+        .iter()
+        .map(|credit| format!("{}{}", credit.name, credit.joinphrase))
+        .collect()
+}
+
+/// Escapes characters with special meaning in MusicBrainz's Lucene queries
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|c| *c != '"')
+        .flat_map(|c| {
+            let special = "+-&|!(){}[]^~*?:\\/".contains(c);
+            special
+                .then_some('\\')
+                .into_iter()
+                .chain(std::iter::once(c))
+        })
+        .collect()
+}
@@ -0,0 +1,46 @@
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::{
+    core::{
+        error::PipeBoomResult,
+        models::{OdesliResults, ShareLinks},
+    },
+    integrations::http::fetch_json,
+};
+
+/// Resolves links to a song on other platforms with the Odesli (song.link) API
+pub struct Odesli {
+    base_url: String,
+    country: String,
+}
+
+impl Odesli {
+    pub fn new(base_url: String, country: String) -> Self {
+        Self { base_url, country }
+    }
+
+    pub async fn links(&self, song_url: &str) -> PipeBoomResult<ShareLinks> {
+        let url = format!(
+            "{}/v1-alpha.1/links?url={}&userCountry={}",
+            self.base_url.trim_end_matches('/'),
+            utf8_percent_encode(song_url, NON_ALPHANUMERIC),
+            self.country.to_uppercase()
+        );
+
+        log::debug!("Requesting Odesli: {}", url);
+
+        let results = fetch_json::<OdesliResults>(surf::get(url)).await?;
+        let platform_url = |platform: &str| {
+            results
+                .links_by_platform
+                .get(platform)
+                .map(|link| link.url.clone())
+        };
+
+        Ok(ShareLinks {
+            universal_url: Some(results.page_url.clone()),
+            spotify_url: platform_url("spotify"),
+            youtube_url: platform_url("youtubeMusic").or_else(|| platform_url("youtube")),
+        })
+    }
+}
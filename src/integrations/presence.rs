@@ -0,0 +1,486 @@
+use std::borrow::Cow;
+
+use discord_rich_presence::activity::{Activity, ActivityType, Assets, Button, Party, Timestamps};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Maximum length of `details`, `state` and image hover texts, in bytes
+pub const TEXT_MAX_BYTES: usize = 128;
+/// Minimum length of `details`, `state` and image hover texts, in characters
+pub const TEXT_MIN_CHARS: usize = 2;
+/// Maximum length of an image asset key or URL, in bytes
+pub const IMAGE_MAX_BYTES: usize = 256;
+/// Maximum length of a button label, in characters
+pub const BUTTON_LABEL_MAX_CHARS: usize = 32;
+/// Maximum length of a button URL, in bytes
+pub const BUTTON_URL_MAX_BYTES: usize = 512;
+/// Maximum number of buttons on an activity
+pub const MAX_BUTTONS: usize = 2;
+/// Asset key used when the large image is missing or invalid
+pub const FALLBACK_IMAGE: &str = "no_art";
+
+const ELLIPSIS: &str = "…";
+const PADDING: char = '\u{200B}';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Measure {
+    Bytes,
+    Chars,
+}
+
+impl Measure {
+    fn of(self, text: &str) -> usize {
+        match self {
+            Measure::Bytes => text.len(),
+            Measure::Chars => text.chars().count(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityButton {
+    pub label: String,
+    pub url: String,
+}
+
+impl ActivityButton {
+    pub fn new(label: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            label: label.into(),
+            url: url.into(),
+        }
+    }
+}
+
+/// Owned description of a Discord activity. Build one freely, then call
+/// [`ActivityPayload::normalized`] before sending it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ActivityPayload {
+    pub details: Option<String>,
+    pub state: Option<String>,
+    pub large_image: Option<String>,
+    pub large_text: Option<String>,
+    pub small_image: Option<String>,
+    pub small_text: Option<String>,
+    pub start_timestamp: Option<i64>,
+    /// Current and maximum party size, rendered as "(current of max)"
+    pub party_size: Option<[u32; 2]>,
+    pub buttons: Vec<ActivityButton>,
+}
+
+impl ActivityPayload {
+    /// Brings every field within Discord's limits. Fields that cannot be
+    /// salvaged are dropped (or replaced, for the large image) and reported
+    /// in the debug log.
+    pub fn normalized(self) -> Self {
+        let large_image = match self.large_image {
+            Some(image) => normalize_image("large_image", &image),
+            None => None,
+        }
+        .or_else(|| Some(FALLBACK_IMAGE.to_string()));
+
+        let mut buttons = self
+            .buttons
+            .iter()
+            .filter_map(normalize_button)
+            .collect::<Vec<_>>();
+
+        if buttons.len() > MAX_BUTTONS {
+            log::debug!(
+                "Rejected Discord field 'buttons': {} buttons given, keeping the first {}",
+                buttons.len(),
+                MAX_BUTTONS
+            );
+            buttons.truncate(MAX_BUTTONS);
+        }
+
+        Self {
+            details: self
+                .details
+                .and_then(|text| normalize_text("details", &text)),
+            state: self.state.and_then(|text| normalize_text("state", &text)),
+            large_image,
+            large_text: self
+                .large_text
+                .and_then(|text| normalize_text("large_text", &text)),
+            small_image: self
+                .small_image
+                .and_then(|image| normalize_image("small_image", &image)),
+            small_text: self
+                .small_text
+                .and_then(|text| normalize_text("small_text", &text)),
+            start_timestamp: self.start_timestamp.filter(|start| *start > 0),
+            party_size: self.party_size.and_then(normalize_party_size),
+            buttons,
+        }
+    }
+
+    pub fn to_activity(&self) -> Activity<'_> {
+        let mut assets = Assets::new();
+        if let Some(image) = &self.large_image {
+            assets = assets.large_image(image.as_str());
+        }
+        if let Some(text) = &self.large_text {
+            assets = assets.large_text(text.as_str());
+        }
+        if let Some(image) = &self.small_image {
+            assets = assets.small_image(image.as_str());
+        }
+        if let Some(text) = &self.small_text {
+            assets = assets.small_text(text.as_str());
+        }
+
+        let mut activity = Activity::new()
+            .activity_type(ActivityType::Listening)
+            .assets(assets);
+
+        if let Some(details) = &self.details {
+            activity = activity.details(details.as_str());
+        }
+        if let Some(state) = &self.state {
+            activity = activity.state(state.as_str());
+        }
+        if let Some(start) = self.start_timestamp {
+            activity = activity.timestamps(Timestamps::new().start(start));
+        }
+        if let Some([current, max]) = self.party_size {
+            activity = activity.party(Party::new().size([current as i32, max as i32]));
+        }
+        if !self.buttons.is_empty() {
+            activity = activity.buttons(
+                self.buttons
+                    .iter()
+                    .map(|button| Button::new(button.label.as_str(), button.url.as_str()))
+                    .collect(),
+            );
+        }
+
+        activity
+    }
+}
+
+/// Shortens `text` to at most `limit` units without splitting a grapheme
+/// cluster, marking the cut with an ellipsis.
+pub fn truncate_graphemes(text: &str, limit: usize, measure: Measure) -> Cow<'_, str> {
+    if measure.of(text) <= limit {
+        return Cow::Borrowed(text);
+    }
+
+    let budget = limit.saturating_sub(measure.of(ELLIPSIS));
+    let mut used = 0;
+    let mut end = 0;
+
+    for (idx, grapheme) in text.grapheme_indices(true) {
+        let size = measure.of(grapheme);
+        if used + size > budget {
+            break;
+        }
+        used += size;
+        end = idx + grapheme.len();
+    }
+
+    Cow::Owned(format!("{}{}", text[..end].trim_end(), ELLIPSIS))
+}
+
+fn normalize_text(field: &str, text: &str) -> Option<String> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        log::debug!("Rejected Discord field '{}': empty text", field);
+        return None;
+    }
+
+    let truncated = truncate_graphemes(trimmed, TEXT_MAX_BYTES, Measure::Bytes);
+    if let Cow::Owned(_) = truncated {
+        log::debug!(
+            "Truncated Discord field '{}' to {} bytes",
+            field,
+            TEXT_MAX_BYTES
+        );
+    }
+
+    let mut normalized = truncated.into_owned();
+    let chars = normalized.chars().count();
+    if chars < TEXT_MIN_CHARS {
+        log::debug!(
+            "Padded Discord field '{}' to {} characters",
+            field,
+            TEXT_MIN_CHARS
+        );
+        normalized.extend(std::iter::repeat_n(PADDING, TEXT_MIN_CHARS - chars));
+    }
+
+    Some(normalized)
+}
+
+fn normalize_image(field: &str, image: &str) -> Option<String> {
+    let image = image.trim();
+
+    if image.is_empty() {
+        log::debug!("Rejected Discord field '{}': empty image", field);
+        return None;
+    }
+
+    if image.len() > IMAGE_MAX_BYTES {
+        log::debug!(
+            "Rejected Discord field '{}': {} bytes exceeds the {} byte limit",
+            field,
+            image.len(),
+            IMAGE_MAX_BYTES
+        );
+        return None;
+    }
+
+    if image.contains("://") {
+        return match validate_url(image) {
+            Ok(()) => Some(image.to_string()),
+            Err(reason) => {
+                log::debug!("Rejected Discord field '{}': {}", field, reason);
+                None
+            }
+        };
+    }
+
+    if image.starts_with("mp:") || image.chars().all(is_asset_key_char) {
+        Some(image.to_string())
+    } else {
+        log::debug!(
+            "Rejected Discord field '{}': '{}' is neither a URL nor an asset key",
+            field,
+            image
+        );
+        None
+    }
+}
+
+fn normalize_button(button: &ActivityButton) -> Option<ActivityButton> {
+    let label = button.label.trim();
+    if label.is_empty() {
+        log::debug!("Rejected Discord button: empty label");
+        return None;
+    }
+
+    let url = button.url.trim();
+    if url.len() > BUTTON_URL_MAX_BYTES {
+        log::debug!(
+            "Rejected Discord button '{}': URL exceeds the {} byte limit",
+            label,
+            BUTTON_URL_MAX_BYTES
+        );
+        return None;
+    }
+
+    if let Err(reason) = validate_url(url) {
+        log::debug!("Rejected Discord button '{}': {}", label, reason);
+        return None;
+    }
+
+    let normalized = truncate_graphemes(label, BUTTON_LABEL_MAX_CHARS, Measure::Chars);
+    if let Cow::Owned(_) = normalized {
+        log::debug!(
+            "Truncated Discord button label '{}' to {} characters",
+            label,
+            BUTTON_LABEL_MAX_CHARS
+        );
+    }
+
+    Some(ActivityButton::new(normalized, url))
+}
+
+fn normalize_party_size([current, max]: [u32; 2]) -> Option<[u32; 2]> {
+    if current == 0 || current > max || max > i32::MAX as u32 {
+        log::debug!(
+            "Rejected Discord field 'party_size': invalid size [{}, {}]",
+            current,
+            max
+        );
+        return None;
+    }
+
+    Some([current, max])
+}
+
+fn validate_url(url: &str) -> Result<(), String> {
+    let parsed = surf::Url::parse(url).map_err(|e| format!("invalid URL '{}': {}", url, e))?;
+
+    if parsed.scheme() != "https" {
+        return Err(format!(
+            "unsupported URL scheme '{}' in '{}'",
+            parsed.scheme(),
+            url
+        ));
+    }
+
+    if parsed.host_str().is_none_or(str::is_empty) {
+        return Err(format!("URL '{}' has no host", url));
+    }
+
+    Ok(())
+}
+
+fn is_asset_key_char(c: char) -> bool {
+    c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAMILY: &str = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}\u{200D}\u{1F466}";
+
+    #[test]
+    fn truncates_on_grapheme_boundaries() {
+        let long_family = format!("ab{}", FAMILY);
+        let cases = [
+            // (text, limit, measure, expected)
+            ("short", 10, Measure::Chars, "short"),
+            ("exactly", 7, Measure::Bytes, "exactly"),
+            (long_family.as_str(), 10, Measure::Bytes, "ab…"),
+            (long_family.as_str(), 26, Measure::Bytes, "ab…"),
+            (
+                long_family.as_str(),
+                27,
+                Measure::Bytes,
+                long_family.as_str(),
+            ),
+            ("abcde\u{301}f", 6, Measure::Chars, "abcd…"),
+            ("abcde\u{301}f", 7, Measure::Chars, "abcde\u{301}f"),
+            ("abcde\u{301}fg", 7, Measure::Chars, "abcde\u{301}…"),
+            ("hello world foo", 7, Measure::Chars, "hello…"),
+            ("hello world", 8, Measure::Chars, "hello w…"),
+            ("héllo wörld", 8, Measure::Bytes, "héll…"),
+        ];
+
+        for (text, limit, measure, expected) in cases {
+            let truncated = truncate_graphemes(text, limit, measure);
+            assert_eq!(
+                truncated, expected,
+                "{:?} cut to {} {:?}",
+                text, limit, measure
+            );
+            assert!(
+                measure.of(&truncated) <= limit,
+                "{:?} exceeds {}",
+                truncated,
+                limit
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_text_fields() {
+        let too_long = "x".repeat(200);
+        let truncated = format!("{}…", "x".repeat(TEXT_MAX_BYTES - ELLIPSIS.len()));
+        let cases = [
+            // (text, expected)
+            ("a", Some("a\u{200B}")),
+            ("  a  ", Some("a\u{200B}")),
+            ("ab", Some("ab")),
+            (FAMILY, Some(FAMILY)),
+            ("", None),
+            ("   ", None),
+            (too_long.as_str(), Some(truncated.as_str())),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(
+                normalize_text("details", text).as_deref(),
+                expected,
+                "{:?}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_buttons() {
+        let url = "https://music.apple.com/song/1";
+        let label_32 = "L".repeat(32);
+        let label_33 = "L".repeat(33);
+        let label_cut = format!("{}…", "L".repeat(31));
+        let long_url = format!("https://example.com/{}", "a".repeat(BUTTON_URL_MAX_BYTES));
+        let cases = [
+            // (label, url, expected label)
+            ("Listen", url, Some("Listen")),
+            (label_32.as_str(), url, Some(label_32.as_str())),
+            (label_33.as_str(), url, Some(label_cut.as_str())),
+            ("", url, None),
+            ("Listen", long_url.as_str(), None),
+            ("Listen", "http://music.apple.com/song/1", None),
+            ("Listen", "ftp://music.apple.com/song/1", None),
+            ("Listen", "javascript:alert(1)", None),
+            ("Listen", "music.apple.com/song/1", None),
+        ];
+
+        for (label, url, expected) in cases {
+            let normalized = normalize_button(&ActivityButton::new(label, url));
+            assert_eq!(
+                normalized.as_ref().map(|button| button.label.as_str()),
+                expected,
+                "{:?} -> {:?}",
+                label,
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_images() {
+        let long_url = format!("https://example.com/{}.jpg", "a".repeat(IMAGE_MAX_BYTES));
+        let cases = [
+            // (image, expected)
+            (
+                "https://example.com/art.jpg",
+                Some("https://example.com/art.jpg"),
+            ),
+            ("http://example.com/art.jpg", None),
+            ("ftp://example.com/art.jpg", None),
+            ("https://", None),
+            (long_url.as_str(), None),
+            ("no_art", Some("no_art")),
+            ("mp:external/abc", Some("mp:external/abc")),
+            ("Not An Asset", None),
+            ("", None),
+        ];
+
+        for (image, expected) in cases {
+            assert_eq!(
+                normalize_image("large_image", image).as_deref(),
+                expected,
+                "{:?}",
+                image
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_whole_payload() {
+        let payload = ActivityPayload {
+            details: Some("a".to_string()),
+            state: Some(" ".to_string()),
+            large_image: Some("http://example.com/art.jpg".to_string()),
+            start_timestamp: Some(0),
+            party_size: Some([3, 2]),
+            buttons: vec![
+                ActivityButton::new("One", "https://example.com/1"),
+                ActivityButton::new("Bad", "http://example.com/2"),
+                ActivityButton::new("Two", "https://example.com/3"),
+                ActivityButton::new("Three", "https://example.com/4"),
+            ],
+            ..Default::default()
+        }
+        .normalized();
+
+        assert_eq!(payload.details.as_deref(), Some("a\u{200B}"));
+        assert_eq!(payload.state, None);
+        assert_eq!(payload.large_image.as_deref(), Some(FALLBACK_IMAGE));
+        assert_eq!(payload.start_timestamp, None);
+        assert_eq!(payload.party_size, None);
+        assert_eq!(
+            payload
+                .buttons
+                .iter()
+                .map(|button| button.label.as_str())
+                .collect::<Vec<_>>(),
+            ["One", "Two"]
+        );
+    }
+}
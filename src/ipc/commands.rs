@@ -1,13 +1,15 @@
-use std::{fs, os::unix::fs::FileTypeExt, path::PathBuf};
+use std::{collections::HashMap, fs, os::unix::fs::FileTypeExt, path::PathBuf};
 
 use crate::core::{
-    error::{PipeBoomError, PipeBoomResult},
-    models::PlayerState,
+    cache::{CacheEntry, CacheStats},
+    error::{ErrorCode, PipeBoomError, PipeBoomResult},
+    models::{PlayerState, ShareLinks},
+    state::{ComponentHealth, ControllerStatus},
 };
 use clap::Subcommand;
 use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
     net::UnixStream,
     sync::oneshot,
 };
@@ -24,6 +26,16 @@ pub enum IpcCommand {
     Status,
     /// Kill PipeBoom daemon
     Shutdown,
+    /// Get metadata cache statistics
+    CacheStats,
+    /// Clear the metadata cache
+    CacheClear,
+    /// Export the metadata cache as JSON
+    CacheExport,
+    /// Get links to the current song on other platforms
+    Share,
+    /// Print controller events as JSON lines until interrupted
+    Events,
 }
 
 #[derive(Debug)]
@@ -32,22 +44,63 @@ pub struct IpcRequest {
     pub response_tx: oneshot::Sender<IpcResponse>,
 }
 
+/// Error sent to IPC clients. `code` is stable and meant for programs, the
+/// message for people.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IpcError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub retryable: bool,
+}
+
+impl From<PipeBoomError> for IpcError {
+    fn from(error: PipeBoomError) -> Self {
+        Self {
+            code: error.code(),
+            message: format!("{:#}", error),
+            retryable: error.is_retryable(),
+        }
+    }
+}
+
+impl From<IpcError> for PipeBoomError {
+    fn from(error: IpcError) -> Self {
+        PipeBoomError::new(error.code, error.message)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum IpcResponse {
     Success,
-    Error(String),
+    Error(IpcError),
     CurrentSong {
         title: Option<String>,
         artist: Option<String>,
         album: Option<String>,
+        primary_artist: Option<String>,
+        featured_artists: Vec<String>,
         state: PlayerState,
+        song_url: Option<String>,
+        links: ShareLinks,
     },
     Status {
         running: bool,
+        /// Controller state, with when each state was entered and its last error
+        controller: ControllerStatus,
+        /// Health of the supervised IPC server and controller tasks
+        components: Vec<ComponentHealth>,
         discord_connected: bool,
         discord_open: bool,
         music_app_open: bool,
     },
+    CacheStats(CacheStats),
+    CacheEntries(HashMap<String, CacheEntry>),
+    Share {
+        title: String,
+        artist: String,
+        song_url: Option<String>,
+        links: ShareLinks,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -55,29 +108,53 @@ pub struct IpcMessage {
     pub command: IpcCommand,
 }
 
-pub async fn send_command(socket_path: PathBuf, command: IpcCommand) -> PipeBoomResult<()> {
+pub async fn send_command(
+    socket_path: PathBuf,
+    command: IpcCommand,
+) -> PipeBoomResult<IpcResponse> {
+    let mut stream = connect(socket_path, command).await?;
+
+    let mut reader = BufReader::new(&mut stream);
+    let mut response = String::new();
+    reader.read_line(&mut response).await?;
+
+    let response: IpcResponse = serde_json::from_str(response.trim())?;
+
+    Ok(response)
+}
+
+/// Subscribes to controller events, which arrive as one JSON object per line
+pub async fn subscribe_events(
+    socket_path: PathBuf,
+) -> PipeBoomResult<Lines<BufReader<UnixStream>>> {
+    let stream = connect(socket_path, IpcCommand::Events).await?;
+    Ok(BufReader::new(stream).lines())
+}
+
+async fn connect(socket_path: PathBuf, command: IpcCommand) -> PipeBoomResult<UnixStream> {
     if !socket_path.exists() {
-        return Err(PipeBoomError::Ipc(format!(
-            "Socket does not exist: {:?}",
-            socket_path
-        )));
+        return Err(PipeBoomError::new(
+            ErrorCode::IpcUnavailable,
+            format!("Socket does not exist: {:?}", socket_path),
+        ));
     }
 
     let socket_meta = fs::metadata(socket_path.clone())?;
     if !socket_meta.file_type().is_socket() {
-        return Err(PipeBoomError::Ipc(format!(
-            "Socket path is not a valid socket: {:?}",
-            socket_path,
-        )));
+        return Err(PipeBoomError::new(
+            ErrorCode::IpcUnavailable,
+            format!("Socket path is not a valid socket: {:?}", socket_path),
+        ));
     }
 
     let mut stream = match UnixStream::connect(&socket_path).await {
         Ok(stream) => stream,
         Err(e) => {
-            return Err(PipeBoomError::Ipc(format!(
-                "Failed to connect to IPC socket: {}",
-                e
-            )));
+            return Err(PipeBoomError::new(
+                ErrorCode::IpcUnavailable,
+                "Failed to connect to IPC socket",
+            )
+            .with_source(e));
         }
     };
 
@@ -87,12 +164,5 @@ pub async fn send_command(socket_path: PathBuf, command: IpcCommand) -> PipeBoom
     stream.write_all(message_json.as_bytes()).await?;
     stream.write_all(b"\n").await?;
 
-    let mut reader = BufReader::new(&mut stream);
-    let mut response = String::new();
-    reader.read_line(&mut response).await?;
-
-    let response: IpcResponse = serde_json::from_str(response.trim())?;
-    println!("{:#?}", response);
-
-    Ok(())
+    Ok(stream)
 }
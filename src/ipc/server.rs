@@ -1,38 +1,52 @@
 use crate::core::error::{PipeBoomError, PipeBoomResult};
-use crate::ipc::commands::{IpcMessage, IpcRequest};
+use crate::core::events::EventBus;
+use crate::ipc::commands::{IpcCommand, IpcMessage, IpcRequest};
 use std::os::unix::fs::PermissionsExt;
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
-use tokio::sync::{mpsc, oneshot};
+use tokio::sync::{broadcast::error::RecvError, mpsc, oneshot};
+use tokio::time::sleep;
+
+/// Failed accepts in a row before the server gives up and is restarted
+const MAX_ACCEPT_FAILURES: u32 = 10;
+/// Wait after a failed accept, multiplied by the failures so far, so running
+/// out of descriptors doesn't turn into a busy loop
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
 
 pub struct IpcServer {
     socket_path: PathBuf,
     request_tx: mpsc::UnboundedSender<IpcRequest>,
+    events: EventBus,
 }
 
 impl IpcServer {
-    pub fn new(socket_path: PathBuf) -> (Self, mpsc::UnboundedReceiver<IpcRequest>) {
+    pub fn new(
+        socket_path: PathBuf,
+        events: EventBus,
+    ) -> (Self, mpsc::UnboundedReceiver<IpcRequest>) {
         let (request_tx, request_rx) = mpsc::unbounded_channel();
 
         (
             Self {
                 socket_path,
                 request_tx,
+                events,
             },
             request_rx,
         )
     }
 
-    pub async fn start(&mut self) -> PipeBoomResult<()> {
+    pub async fn start(&self) -> PipeBoomResult<()> {
         if self.socket_path.exists() {
             std::fs::remove_file(&self.socket_path).map_err(|e| {
-                PipeBoomError::Ipc(format!("Failed to remove existing socket: {}", e))
+                PipeBoomError::ipc("Failed to remove existing socket").with_source(e)
             })?;
         }
 
         let listener = UnixListener::bind(&self.socket_path)
-            .map_err(|e| PipeBoomError::Ipc(format!("Failed to bind Unix socket: {}", e)))?;
+            .map_err(|e| PipeBoomError::ipc("Failed to bind Unix socket").with_source(e))?;
 
         if let Err(e) = self.set_basic_permissions() {
             log::warn!("Failed to set socket permissions: {}", e);
@@ -40,19 +54,32 @@ impl IpcServer {
 
         log::info!("IPC server listening on {:?}", self.socket_path);
 
+        let mut failures = 0;
         loop {
             match listener.accept().await {
                 Ok((stream, _)) => {
+                    failures = 0;
                     let request_tx = self.request_tx.clone();
+                    let events = self.events.clone();
 
                     tokio::spawn(async move {
-                        if let Err(e) = Self::handle_client(stream, request_tx).await {
-                            log::error!("Client handler error: {}", e);
+                        if let Err(e) = Self::handle_client(stream, request_tx, events).await {
+                            log::error!("Client handler error: {:#}", e);
                         }
                     });
                 }
                 Err(e) => {
+                    failures += 1;
+                    if failures >= MAX_ACCEPT_FAILURES {
+                        return Err(PipeBoomError::ipc(format!(
+                            "Failed to accept {} connections in a row",
+                            failures
+                        ))
+                        .with_source(e));
+                    }
+
                     log::error!("Failed to accept connection: {}", e);
+                    sleep(ACCEPT_BACKOFF * failures).await;
                 }
             }
         }
@@ -61,6 +88,7 @@ impl IpcServer {
     async fn handle_client(
         mut stream: UnixStream,
         request_tx: mpsc::UnboundedSender<IpcRequest>,
+        events: EventBus,
     ) -> PipeBoomResult<()> {
         let mut reader = BufReader::new(&mut stream);
         let mut line = String::new();
@@ -69,9 +97,13 @@ impl IpcServer {
             Ok(0) => return Ok(()),
             Ok(_) => {
                 let message = serde_json::from_str::<IpcMessage>(line.trim()).map_err(|e| {
-                    PipeBoomError::Ipc(format!("Failed to parse IPC message: {}", e))
+                    PipeBoomError::ipc("Failed to parse IPC message").with_source(e)
                 })?;
 
+                if let IpcCommand::Events = message.command {
+                    return Self::stream_events(stream, events).await;
+                }
+
                 let (response_tx, response_rx) = oneshot::channel();
 
                 let request = IpcRequest {
@@ -81,36 +113,59 @@ impl IpcServer {
 
                 request_tx
                     .send(request)
-                    .map_err(|e| PipeBoomError::Ipc(format!("Failed to send request: {}", e)))?;
+                    .map_err(|e| PipeBoomError::ipc("Failed to send request").with_source(e))?;
 
-                let response = response_rx.await.map_err(|e| {
-                    PipeBoomError::Ipc(format!("Failed to receive response: {}", e))
-                })?;
+                let response = response_rx
+                    .await
+                    .map_err(|e| PipeBoomError::ipc("Failed to receive response").with_source(e))?;
 
                 let response_json = serde_json::to_string(&response).map_err(|e| {
-                    PipeBoomError::Ipc(format!("Failed to serialize response: {}", e))
+                    PipeBoomError::ipc("Failed to serialize response").with_source(e)
                 })?;
 
                 stream
                     .write_all(response_json.as_bytes())
                     .await
-                    .map_err(|e| PipeBoomError::Ipc(format!("Failed to write response: {}", e)))?;
+                    .map_err(|e| PipeBoomError::ipc("Failed to write response").with_source(e))?;
                 stream
                     .write_all(b"\n")
                     .await
-                    .map_err(|e| PipeBoomError::Ipc(format!("Failed to write newline: {}", e)))?;
+                    .map_err(|e| PipeBoomError::ipc("Failed to write newline").with_source(e))?;
             }
             Err(e) => {
-                return Err(PipeBoomError::Ipc(format!(
-                    "Failed to read from client: {}",
-                    e
-                )));
+                return Err(PipeBoomError::ipc("Failed to read from client").with_source(e));
             }
         }
 
         Ok(())
     }
 
+    /// Writes each event to the client until it disconnects
+    async fn stream_events(mut stream: UnixStream, events: EventBus) -> PipeBoomResult<()> {
+        log::debug!("IPC client subscribed to events");
+        let mut events = events.subscribe();
+
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    log::warn!("IPC event subscriber missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            };
+
+            let mut event_json = serde_json::to_string(&event)
+                .map_err(|e| PipeBoomError::ipc("Failed to serialize event").with_source(e))?;
+            event_json.push('\n');
+
+            if stream.write_all(event_json.as_bytes()).await.is_err() {
+                log::debug!("IPC event subscriber disconnected");
+                return Ok(());
+            }
+        }
+    }
+
     fn set_basic_permissions(&self) -> Result<(), std::io::Error> {
         let metadata = std::fs::metadata(&self.socket_path)?;
         let mut permissions = metadata.permissions();
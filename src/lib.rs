@@ -0,0 +1,4 @@
+pub mod app;
+pub mod core;
+pub mod integrations;
+pub mod ipc;
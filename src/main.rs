@@ -1,34 +1,71 @@
-mod app;
-mod core;
-mod integrations;
-mod ipc;
+use std::{path, process::ExitCode};
 
-use app::{
-    App,
-    cli::{Cli, CliCommand},
-    setup::{setup_launch_agent, uninstall_launch_agent},
-};
 use clap::Parser;
-use core::{
-    error::{PipeBoomError, PipeBoomResult},
-    logging::setup_logging,
+use pipeboom::{
+    app::{
+        App,
+        cache::run_cache_command,
+        cli::{Cli, CliCommand},
+        instance::{InstanceLock, daemonize},
+        overrides::run_overrides_command,
+        setup::{setup_launch_agent, uninstall_launch_agent},
+    },
+    core::{config::Config, error::PipeBoomResult, logging::setup_logging},
+    integrations::http::configure as configure_http,
+    ipc::commands::{IpcCommand, send_command, subscribe_events},
 };
-use ipc::commands::{IpcCommand, send_command};
 
-#[tokio::main]
-async fn main() -> PipeBoomResult<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn try_main() -> PipeBoomResult<()> {
+    let mut cli = Cli::parse();
+
+    // The service takes the instance lock, and detaches before the runtime
+    // starts any threads
+    let lock = if cli.command.is_none() {
+        if cli.daemon {
+            // The daemon moves to `/`, so relative paths would point elsewhere
+            cli.pid_file = path::absolute(&cli.pid_file)?;
+            cli.socket_path = path::absolute(&cli.socket_path)?;
+            cli.config = path::absolute(&cli.config)?;
+        }
+
+        let mut lock = InstanceLock::acquire(&cli.pid_file)?;
+        if cli.daemon {
+            daemonize()?;
+            lock.write_pid()?;
+        }
+        Some(lock)
+    } else {
+        None
+    };
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?
+        .block_on(run(cli, lock))
+}
 
+async fn run(cli: Cli, lock: Option<InstanceLock>) -> PipeBoomResult<()> {
     let poll_interval = cli.poll_interval;
     let log_level = cli.log_level;
     let max_log_size = cli.max_log_size;
     let socket_path = cli.socket_path;
+    let config_path = cli.config;
 
-    setup_logging(log_level.into(), max_log_size).map_err(|e| {
-        eprintln!("Failed to initialize logging: {}", e);
-        PipeBoomError::Config("Failed to initialize logging".to_string())
-    })?;
+    setup_logging(log_level.into(), max_log_size)
+        .map_err(|e| e.context("Failed to initialize logging"))?;
 
+    // Only the service and the commands that read settings load the config,
+    // so an invalid file can't get in the way of setup or stopping the service
     if let Some(command) = cli.command {
         match command {
             CliCommand::Setup => setup_launch_agent()?,
@@ -38,26 +75,56 @@ async fn main() -> PipeBoomResult<()> {
                 | IpcCommand::Stop
                 | IpcCommand::CurrentSong
                 | IpcCommand::Status
-                | IpcCommand::Shutdown => send_command(socket_path, ipc_command).await?,
+                | IpcCommand::Shutdown
+                | IpcCommand::CacheStats
+                | IpcCommand::CacheClear
+                | IpcCommand::CacheExport
+                | IpcCommand::Share => {
+                    let response = send_command(socket_path, ipc_command).await?;
+                    println!("{:#?}", response);
+                }
+                IpcCommand::Events => {
+                    let mut events = subscribe_events(socket_path).await?;
+                    while let Some(event) = events.next_line().await? {
+                        println!("{}", event);
+                    }
+                }
             },
+            CliCommand::Cache(cache_command) => {
+                run_cache_command(cache_command, socket_path, &config_path).await?
+            }
+            CliCommand::Overrides(overrides_command) => {
+                run_overrides_command(overrides_command, &Config::load(&config_path)?)?
+            }
         }
 
         Ok(())
     } else {
+        let config = Config::load(&config_path)?;
+        configure_http(&config.http)?;
+        config.polling.check_interval(poll_interval)?;
+
         let mut app = App::default();
         log::info!("Starting PipeBoom v{}", env!("CARGO_PKG_VERSION"));
         log::info!("Using IPC socket at {:?}", socket_path);
         log::info!("Polling interval: {:?}", poll_interval);
         log::info!("Log level: {:?}", log_level);
         log::info!("Max log size: {}MB", max_log_size);
+        log::info!("Config file: {:?}", config_path);
+        if let Some(pid) = lock.as_ref().and_then(InstanceLock::stale_pid) {
+            log::info!("Recovered stale lock left by PID {}", pid);
+        }
 
-        match app.run(poll_interval, socket_path).await {
+        match app
+            .run(poll_interval, socket_path, config, config_path)
+            .await
+        {
             Ok(_) => {
                 log::info!("PipeBoom shut down successfully");
                 Ok(())
             }
             Err(e) => {
-                log::error!("PipeBoom error: {}", e);
+                log::error!("PipeBoom error: {:#}", e);
                 Err(e)
             }
         }
@@ -0,0 +1,105 @@
+//! Metadata providers against responses recorded in `tests/fixtures/http`
+
+mod common;
+
+use std::{path::PathBuf, sync::Once};
+
+use common::song;
+use pipeboom::{
+    core::{
+        config::{EndpointsConfig, FixtureMode, HttpConfig, Storefront},
+        error::ErrorCode,
+    },
+    integrations::{
+        deezer::Deezer, http::configure, itunes_api::ItunesSearch, metadata::MetadataProvider,
+        musicbrainz::MusicBrainz,
+    },
+};
+
+fn replay() -> EndpointsConfig {
+    static CONFIGURE: Once = Once::new();
+    CONFIGURE.call_once(|| {
+        configure(&HttpConfig {
+            fixtures: FixtureMode::Replay,
+            fixtures_dir: Some(
+                PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/http"),
+            ),
+            ..Default::default()
+        })
+        .unwrap();
+    });
+
+    EndpointsConfig::default()
+}
+
+#[tokio::test]
+async fn replays_itunes_search() {
+    let endpoints = replay();
+    let provider = ItunesSearch::new(
+        endpoints.itunes,
+        Storefront {
+            country: "us".to_string(),
+            language: None,
+            fallback_country: None,
+        },
+    );
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(details.catalog_id, Some(1116873776));
+}
+
+#[tokio::test]
+async fn replays_itunes_throttling_as_rate_limits() {
+    let endpoints = replay();
+    let provider = ItunesSearch::new(
+        endpoints.itunes,
+        Storefront {
+            country: "us".to_string(),
+            language: None,
+            fallback_country: None,
+        },
+    );
+
+    let error = provider
+        .get_details(&song("Throttled", "Song", "Album", 200.0), None)
+        .await
+        .unwrap_err();
+
+    assert_eq!(error.code(), ErrorCode::LookupRateLimited);
+}
+
+#[tokio::test]
+async fn replays_musicbrainz_and_cover_art_redirect() {
+    let endpoints = replay();
+    let provider = MusicBrainz::new(endpoints.musicbrainz, endpoints.cover_art_archive);
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        details.artwork,
+        "https://coverartarchive.org/release/9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11/6208393741-500.jpg"
+    );
+}
+
+#[tokio::test]
+async fn replays_deezer_search() {
+    let endpoints = replay();
+    let provider = Deezer::new(endpoints.deezer);
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(details.song_url, "https://www.deezer.com/track/1174602");
+}
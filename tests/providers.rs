@@ -0,0 +1,308 @@
+//! Metadata providers against local stub servers
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use common::{StubResponse, StubServer, fixture, song, temp_path};
+use pipeboom::{
+    core::{
+        cache::MetadataCache,
+        config::{AppleMusicConfig, CacheConfig, Config, ProviderKind, Storefront},
+        error::ErrorCode,
+        models::SongDetails,
+    },
+    integrations::{
+        apple_music_api::AppleMusicApi,
+        deezer::Deezer,
+        itunes_api::{ItunesLookup, ItunesSearch},
+        metadata::{MetadataProvider, ProviderChain},
+        musicbrainz::MusicBrainz,
+    },
+};
+
+fn storefront() -> Storefront {
+    Storefront {
+        country: "us".to_string(),
+        language: None,
+        fallback_country: None,
+    }
+}
+
+fn search_response() -> String {
+    let fixture =
+        serde_json::from_str::<serde_json::Value>(&fixture("matching/remaster.json")).unwrap();
+    fixture["response"].to_string()
+}
+
+#[tokio::test]
+async fn itunes_search_picks_the_best_match() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/search?") => StubResponse::json(search_response()),
+        _ => StubResponse::status(404),
+    });
+    let provider = ItunesSearch::new(server.url.clone(), storefront());
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(details.catalog_id, Some(1116873776));
+    assert!(details.song_url.contains("i=1116873776"));
+    assert_eq!(details.track_number, Some(2));
+    assert!(server.requests()[0].contains("entity=song"));
+}
+
+#[tokio::test]
+async fn itunes_search_encodes_ampersands_in_band_names() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/search?") => StubResponse::json(r#"{"resultCount":0,"results":[]}"#),
+        _ => StubResponse::status(404),
+    });
+    let provider = ItunesSearch::new(server.url.clone(), storefront());
+
+    let details = provider
+        .get_details(
+            &song(
+                "Simon & Garfunkel",
+                "The Boxer",
+                "Bridge Over Troubled Water",
+                308.0,
+            ),
+            None,
+        )
+        .await
+        .unwrap();
+
+    assert!(details.is_none());
+    let requests = server.requests();
+    assert_eq!(requests.len(), 2);
+    for request in requests {
+        assert!(
+            request.contains("&term=Simon%20%26%20Garfunkel%20"),
+            "{}",
+            request
+        );
+        assert!(request.ends_with("&country=us"), "{}", request);
+    }
+}
+
+#[tokio::test]
+async fn itunes_lookup_uses_the_known_catalog_id() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/lookup?") && t.contains("id=1116873776") => {
+            StubResponse::json(fixture("stubs/itunes_lookup.json"))
+        }
+        _ => StubResponse::status(404),
+    });
+    let provider = ItunesLookup::new(server.url.clone(), storefront());
+    let song = song("Fleetwood Mac", "Dreams", "Rumours", 257.8);
+
+    assert!(provider.get_details(&song, None).await.unwrap().is_none());
+    assert!(server.requests().is_empty());
+
+    let mut known = SongDetails::new(String::new(), String::new(), String::new());
+    known.catalog_id = Some(1116873776);
+    let details = provider
+        .get_details(&song, Some(&known))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(details.catalog_id, Some(1116873776));
+    assert!(details.album_url.starts_with("https://music.apple.com/"));
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn apple_music_uses_the_known_isrc_and_falls_back_to_another_storefront() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/v1/catalog/gb/songs?filter[isrc]=USWB10400049") => {
+            StubResponse::json(fixture("stubs/apple_music_songs.json"))
+        }
+        t if t.starts_with("/v1/catalog/") => StubResponse::json(r#"{"data":[],"results":{}}"#),
+        _ => StubResponse::status(404),
+    });
+    let provider = AppleMusicApi::new(
+        server.url.clone(),
+        Storefront {
+            country: "us".to_string(),
+            language: None,
+            fallback_country: Some("gb".to_string()),
+        },
+        &AppleMusicConfig {
+            developer_token: Some("token".to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut known = SongDetails::new(String::new(), String::new(), String::new());
+    known.isrc = Some("USWB10400049".to_string());
+
+    let details = provider
+        .get_details(
+            &song("Fleetwood Mac", "Dreams", "Rumours", 257.8),
+            Some(&known),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(details.catalog_id, Some(1116873776));
+    assert_eq!(details.isrc.as_deref(), Some("USWB10400049"));
+    assert_eq!(details.genres, ["Rock"]);
+    let requests = server.requests();
+    assert!(requests[0].starts_with("/v1/catalog/us/songs?filter[isrc]="));
+    assert!(requests[1].starts_with("/v1/catalog/us/search?"));
+    assert!(requests[2].starts_with("/v1/catalog/gb/songs?filter[isrc]="));
+    assert_eq!(requests.len(), 3);
+}
+
+#[tokio::test]
+async fn musicbrainz_follows_cover_art_redirects() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/ws/2/recording?") => {
+            StubResponse::json(fixture("stubs/musicbrainz_recordings.json"))
+        }
+        "/release/9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11" => StubResponse::status(307).header(
+            "Location",
+            "/download/mbid-9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11/index.json",
+        ),
+        "/download/mbid-9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11/index.json" => {
+            StubResponse::json(fixture("stubs/cover_art_release.json"))
+        }
+        _ => StubResponse::status(404),
+    });
+    let provider = MusicBrainz::new(server.url.clone(), server.url.clone());
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(
+        details.artwork,
+        "https://coverartarchive.org/release/9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11/6208393741-500.jpg"
+    );
+    assert_eq!(
+        details.album_url,
+        "https://musicbrainz.org/release/9ee7b3c2-2a6e-4b5e-9d8f-0c9e2f4a7b11"
+    );
+}
+
+#[tokio::test]
+async fn musicbrainz_without_cover_art_has_no_artwork() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/ws/2/recording?") => {
+            StubResponse::json(fixture("stubs/musicbrainz_recordings.json"))
+        }
+        _ => StubResponse::status(404),
+    });
+    let provider = MusicBrainz::new(server.url.clone(), server.url.clone());
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(details.artwork.is_empty());
+    assert_eq!(
+        details.song_url,
+        "https://musicbrainz.org/recording/a9eb0d1e-d2ab-4a04-8a2e-7d4c5b3d8ea3"
+    );
+    // Both releases were checked for cover art
+    assert_eq!(
+        server
+            .requests()
+            .iter()
+            .filter(|t| t.starts_with("/release/"))
+            .count(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn deezer_picks_the_best_match() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/search?") => StubResponse::json(fixture("stubs/deezer_search.json")),
+        _ => StubResponse::status(404),
+    });
+    let provider = Deezer::new(server.url.clone());
+
+    let details = provider
+        .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(details.artwork.contains("1000x1000"));
+    assert_eq!(details.song_url, "https://www.deezer.com/track/1174602");
+}
+
+#[tokio::test]
+async fn only_throttling_statuses_are_rate_limits_outside_apple() {
+    // (status, retry_after, expected)
+    let cases = [
+        (429, None, ErrorCode::LookupRateLimited),
+        (429, Some("120"), ErrorCode::LookupRateLimited),
+        (503, Some("30"), ErrorCode::LookupRateLimited),
+        (503, None, ErrorCode::LookupNetwork),
+        (403, None, ErrorCode::LookupNetwork),
+        (403, Some("30"), ErrorCode::LookupNetwork),
+    ];
+
+    for (status, retry_after, expected) in cases {
+        let server = StubServer::start(move |_| match retry_after {
+            Some(seconds) => StubResponse::status(status).header("Retry-After", seconds),
+            None => StubResponse::status(status),
+        });
+        let provider = Deezer::new(server.url.clone());
+
+        let error = provider
+            .get_details(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8), None)
+            .await
+            .unwrap_err();
+
+        assert_eq!(error.code(), expected, "{} {:?}", status, retry_after);
+    }
+}
+
+#[tokio::test]
+async fn chain_links_other_providers_to_an_apple_music_search() {
+    let server = StubServer::start(|target| match target {
+        t if t.starts_with("/search?") => StubResponse::json(fixture("stubs/deezer_search.json")),
+        _ => StubResponse::status(404),
+    });
+
+    let mut config = Config::default();
+    config.lookup.country = "us".to_string();
+    config.lookup.language = "en".to_string();
+    config.lookup.providers = vec![ProviderKind::Deezer];
+    config.lookup.endpoints.deezer = server.url.clone();
+    let cache = MetadataCache::load(&CacheConfig {
+        path: Some(temp_path("chain-cache.json")),
+        ..Default::default()
+    });
+    let chain = ProviderChain::new(&config, Arc::new(Mutex::new(cache)));
+
+    let details = chain
+        .lookup(&song("Fleetwood Mac", "Dreams", "Rumours", 257.8))
+        .await;
+
+    assert_eq!(details.provider.as_deref(), Some("deezer"));
+    assert!(details.song_url.is_empty());
+    assert_eq!(
+        details.album_url,
+        "https://music.apple.com/us/search?term=Fleetwood%20Mac%20Dreams"
+    );
+    assert_eq!(
+        details.source_url.as_deref(),
+        Some("https://www.deezer.com/track/1174602")
+    );
+
+    let _ = std::fs::remove_file(temp_path("chain-cache.json"));
+}